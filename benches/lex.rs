@@ -0,0 +1,74 @@
+// 大模板上的词法分析吞吐量测试: cargo bench --bench lex
+#[macro_use]
+extern crate criterion;
+extern crate parse;
+
+use criterion::{Criterion, Throughput};
+use parse::parse::lex::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 统计堆分配的次数，看词法分析每个 token 要分配几次
+struct CountingAlloc;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8{
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout){
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// 把一段模板重复到至少 size 字节
+fn repeat_to(chunk: &str, size: usize) -> String{
+    let mut s = String::with_capacity(size + chunk.len());
+    while s.len() < size{
+        s.push_str(chunk);
+    }
+    s
+}
+
+fn templates() -> Vec<(&'static str, String)>{
+    let size = 4 << 20;
+    vec![
+        // 大段的纯文本，只有很少的 action
+        ("text", repeat_to("Lorem ipsum dolor sit amet, consectetur adipiscing elit.\n{{.Name}}\n", size)),
+        // 几乎全是 action
+        ("actions", repeat_to("{{if .X}}{{range $i, $v := .Items}}{{$v.Name | printf \"%s-%d\" $i}}{{end}}{{else}}{{- 3.5e2 -}}{{end}}\n", size)),
+        // 多字节字符
+        ("unicode", repeat_to("模板里的中文文本，{{.名字}} 和 {{printf \"%v\" .值}} ünïcödé\n", size)),
+        // 注释和 trim 标记
+        ("comments", repeat_to("  {{- /* a comment that is fairly long */ -}}  {{/* x */}}\n", size)),
+    ]
+}
+
+fn bench_lex(c: &mut Criterion){
+    let mut group = c.benchmark_group("lex");
+    group.sample_size(10);
+    for (name, input) in templates(){
+        // 确保整个模板都被扫描了，而不是中途出错提前结束
+        let last = lex(name, &input, "", "").last().unwrap();
+        assert!(last.typ == ItemType::ItemEOF, "{}: {:?}", name, last);
+        let before = ALLOCS.load(Ordering::Relaxed);
+        let tokens = lex(name, &input, "", "").count();
+        let allocs = ALLOCS.load(Ordering::Relaxed) - before;
+        println!("lex/{}: {} tokens, {:.2} allocations per token", name, tokens, allocs as f64 / tokens as f64);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function(name, |b| b.iter(|| {
+            let l = lex(name, &input, "", "");
+            l.count()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lex);
+criterion_main!(benches);
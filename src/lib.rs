@@ -0,0 +1,2 @@
+extern crate unicode_general_category;
+pub mod parse;
@@ -1,9 +1,4 @@
 // This code is editable and runnable!
-#[macro_use]
-extern crate lazy_static;
-mod parse;
-use parse::lex::*;
-use parse::parse::*;
-use parse::node::*;
+extern crate parse;
 fn main(){
 }
@@ -1,39 +1,61 @@
-use super::node::*;
-use std::rc::Rc;
+use super::node::Pos;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use unicode_general_category::{get_general_category, GeneralCategory};
 
-#[derive(Debug)]
-pub struct Item{
+// val 直接借用模板的源码，只有错误信息是新分配的字符串，
+// 需要让 item 活得比模板更久时用 into_owned 转换
+#[derive(Debug, Clone)]
+pub struct Item<'a>{
     pub typ: ItemType,
     pub pos: Pos,
-    pub val: String,
+    pub val: Cow<'a, str>,
+    pub line: usize, // item 起始位置所在的行，从 1 开始
+    pub col: usize,  // item 起始位置在该行的第几个字符，从 1 开始
+}
+
+impl<'a> Item<'a>{
+    pub fn into_owned(self) -> Item<'static>{
+        Item{
+            typ: self.typ,
+            pos: self.pos,
+            val: Cow::Owned(self.val.into_owned()),
+            line: self.line,
+            col: self.col,
+        }
+    }
 }
 
-impl Item{
-    //函数的参数尽量使&str, 返回值却是要尽量为String，因为调用者需要所有权？
-    fn to_string(&self) -> String{
+// 和 Go 的 item.String() 一样: EOF、错误信息原样输出、关键字加尖括号，
+// 其余的值加上引号，超过 10 个字符的只显示前 10 个
+impl<'a> fmt::Display for Item<'a>{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
         match self.typ{
-            ItemType::ItemEOF => String::from("EOF"),
-            ItemType::ItemError => format!("{}", self.val),
-            ItemType::ItemKeyword => format!("<{}>", self.val),
+            ItemType::ItemEOF => write!(f, "EOF"),
+            ItemType::ItemError => write!(f, "{}", self.val),
+            k if k > ItemType::ItemKeyword => write!(f, "<{}>", self.val),
             _ => {
                 if self.val.len() > 10{
-                    return format!("{:.10?}", self.val);
-                }else{
-                    return format!("{:?}", self.val);
+                    let head: String = self.val.chars().take(10).collect();
+                    return write!(f, "{}...", quote(&head));
                 }
+                write!(f, "{}", quote(&self.val))
             }
         }
     }
 }
 
-#[derive(Hash, Debug, Eq, PartialEq, PartialOrd)]
+#[derive(Hash, Debug, Clone, Copy, Eq, PartialEq, PartialOrd)]
 pub enum ItemType{
     ItemError,
     ItemBool,
     ItemChar,
     ItemCharConstant,
+    ItemComment,     // 注释，只有打开 emit_comments 时才会产生
     ItemComplex,
-    ItemColonEquals,
+    ItemAssign,      // = 给已经声明的变量重新赋值
+    ItemColonEquals, // := 声明变量
     ItemEOF,
     ItemField,
     ItemIdentifier,
@@ -47,8 +69,12 @@ pub enum ItemType{
     ItemSpace,
     ItemString,
     ItemText,
+    ItemTrivia,      // 被丢弃的源码，如 trim 掉的空白、trim 标记和注释，只在 lossless 模式下产生
     ItemVariable,
     ItemKeyword,
+    ItemBlock,
+    ItemBreak,
+    ItemContinue,
     ItemDot,
     ItemDefine,
     ItemElse,
@@ -60,137 +86,230 @@ pub enum ItemType{
     ItemWith
 }
 
-const LEFTDELIM: &'static str = "{{";
-const RIGHTDELIM: &'static str = "}}";
-const LEFTCOMMENT: &'static str = "/*";
-const RIGHTCOMMENT: &'static str = "*/";
+impl fmt::Display for ItemType{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        let name = match *self{
+            ItemType::ItemError => "error",
+            ItemType::ItemBool => "bool",
+            ItemType::ItemChar => "char",
+            ItemType::ItemCharConstant => "charconst",
+            ItemType::ItemComment => "comment",
+            ItemType::ItemComplex => "complex",
+            ItemType::ItemAssign => "=",
+            ItemType::ItemColonEquals => ":=",
+            ItemType::ItemEOF => "EOF",
+            ItemType::ItemField => "field",
+            ItemType::ItemIdentifier => "identifier",
+            ItemType::ItemLeftDelim => "left delim",
+            ItemType::ItemLeftParen => "(",
+            ItemType::ItemNumber => "number",
+            ItemType::ItemPipe => "pipe",
+            ItemType::ItemRawString => "raw string",
+            ItemType::ItemRightDelim => "right delim",
+            ItemType::ItemRightParen => ")",
+            ItemType::ItemSpace => "space",
+            ItemType::ItemString => "string",
+            ItemType::ItemText => "text",
+            ItemType::ItemTrivia => "trivia",
+            ItemType::ItemVariable => "variable",
+            ItemType::ItemKeyword => "keyword",
+            ItemType::ItemBlock => "block",
+            ItemType::ItemBreak => "break",
+            ItemType::ItemContinue => "continue",
+            ItemType::ItemDot => ".",
+            ItemType::ItemDefine => "define",
+            ItemType::ItemElse => "else",
+            ItemType::ItemEnd => "end",
+            ItemType::ItemIf => "if",
+            ItemType::ItemNil => "nil",
+            ItemType::ItemRange => "range",
+            ItemType::ItemTemplate => "template",
+            ItemType::ItemWith => "with",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+const LEFTDELIM: &str = "{{";
+const RIGHTDELIM: &str = "}}";
+const LEFTCOMMENT: &str = "/*";
+const RIGHTCOMMENT: &str = "*/";
+// 紧贴着分隔符的 '-' 加上一个空格，例如 "{{- " 和 " -}}"，用来去掉模板文本两边的空白
+const TRIMMARKER: u8 = b'-';
+const TRIMMARKERLEN: Pos = 2;
+// 被 trim 掉的空白字符，和 Go 一样包括换行
+const SPACECHARS: &[char] = &[' ', '\t', '\r', '\n'];
+// 数字里允许用 _ 分隔，如 1_000_000
+const DECIMALDIGITS: &str = "0123456789_";
+const HEXDIGITS: &str = "0123456789abcdefABCDEF_";
+const OCTALDIGITS: &str = "01234567_";
+const BINARYDIGITS: &str = "01_";
 
 #[derive(Debug)]
-pub struct Lexer{
-    pub name: &'static str,
-    pub input: &'static str,
-    pub left_delim: &'static str,
-    pub right_delim: &'static str,
+pub struct Lexer<'a>{
+    pub name: &'a str,
+    pub input: &'a str,
+    pub left_delim: &'a str,
+    pub right_delim: &'a str,
+    pub left_comment: &'a str,
+    pub right_comment: &'a str,
     pub start: Pos,
     pub pos: Pos,
     pub width: Pos,
     pub last_pos: Pos,
     pub paren_depth: i32,
-    pub items: Channel,
+    // 出错后不停止，跳过出错的部分继续扫描，这样一次就能报告模板里所有的词法错误
+    pub recover: bool,
+    // action 里是否允许换行，不允许时遇到换行报 unclosed action（Go 1.16 以前的行为）
+    pub allow_newlines: bool,
+    // 是否把注释作为 ItemComment 发出，默认和 Go 一样直接丢掉
+    pub emit_comments: bool,
+    // 无损模式: 所有被丢弃的源码都作为 ItemTrivia 发出，
+    // 除 ItemError 以外所有 item 的 val 依次拼接起来就是原始的输入。
+    // 没有打开 recover 时，出错后剩下的输入作为一个 ItemTrivia 跟在 ItemError 后面发出
+    pub lossless: bool,
+    // 被关闭的扩展关键字当作普通的 identifier 处理，核心关键字放在这里不起作用
+    pub disabled_keywords: Vec<ItemType>,
+    pub items: Channel<'a>,
+    // line、col 是 line_pos 处的行号和列号，只会随着 item 的产生往后推进
+    line: usize,
+    col: usize,
+    line_pos: Pos,
+    // 下一步要执行的状态，None 表示已经到达 EOF 或者出错
+    state: Option<State>,
 }
 
 
+// 只缓存状态函数已经产生、但调用者还没取走的 item，
+// 一个状态一次最多产生几个 item，不会把整个模板的 token 都存下来
 #[derive(Debug)]
-pub struct Channel{
-    pub index: usize,
-    pub items: Vec<Rc<Item>>
+pub struct Channel<'a>{
+    pub items: VecDeque<Item<'a>>
 }
-impl Channel{
-    fn push(&mut self, item: Item){
-        self.items.push(Rc::new(item));
+impl<'a> Channel<'a>{
+    fn push(&mut self, item: Item<'a>){
+        self.items.push_back(item);
     }    
 
-    fn next(&mut self) -> Option<Rc<Item>>{
-        if self.items.len() > self.index{
-            let item = self.items.get(self.index);
-            self.index = self.index + 1;
-            return Some(item.unwrap().clone());
+    fn next(&mut self) -> Option<Item<'a>>{
+        self.items.pop_front()
+    }
+
+    fn last_is_error(&self) -> bool{
+        match self.items.back(){
+            Some(item) => item.typ == ItemType::ItemError,
+            None => false,
         }
-        return None;
     }
 }
 
-impl Lexer{
+impl<'a> Lexer<'a>{
     fn emit(&mut self, t: ItemType){
-        let item = Item{typ: t, pos: self.start, val: String::from(&self.input[self.start..self.pos])};
+        let start = self.start;
+        let (line, col) = self.line_col(start);
+        let item = Item{typ: t, pos: start, val: Cow::Borrowed(&self.input[start..self.pos]), line, col};
         self.items.push(item);
         self.start = self.pos;
     }
 
     fn next(&mut self)->Option<char>{
-        if self.pos >= self.input.len(){
-            self.width = 0;
-            return None;
-        }
-        let length = self.input.len();
-        let input = &self.input[self.pos..length];
-        let mut i = 0;
-        while i < length{
-            let whether = is_char_boundary(input, i);
-            i = i + 1;
-            if whether{
-                // 判断下一位是不是一个char
-                // 不是的话，说明可能是unicode 3-byte
-                // 或者 4-byte的字符
-                if is_char_boundary(input, i){
-                    // i = i - 1;
-                    //对于 "本a",经历了is_char_boundary==False,需要减一
-                    if i > 1{
-                        i = i - 1;
-                    }
-                    break;
-                }
+        // 直接从当前的字节位置解码一个 UTF-8 字符，pos 总是落在字符边界上
+        match self.input[self.pos..].chars().next(){
+            None => {
+                self.width = 0;
+                None
+            },
+            Some(c) => {
+                self.width = c.len_utf8();
+                self.pos += self.width;
+                Some(c)
             }
         }
-        self.width = i;
-        let out = &self.input[self.pos..self.pos+i];
-        self.pos = self.pos + i;
-        let outchar: Vec<char> = out.chars().collect(); 
-        return Some(outchar[0]);
     }
     
     fn errorf(&mut self, error: String){
         // Rust 不支持 variadic parameters（E0045),所以只能由使用者先处理好错误信息了
-        let item = Item{typ: ItemType::ItemError, pos: self.start, val:error};
+        let start = self.start;
+        let (line, col) = self.line_col(start);
+        let item = Item{typ: ItemType::ItemError, pos: start, val: Cow::Owned(error), line, col};
         self.items.push(item);
     }
 
     fn ignore(&mut self){
+        if self.lossless && self.pos > self.start{
+            self.emit(ItemType::ItemTrivia);
+            return;
+        }
         self.start = self.pos;
     }
 
     fn peek(&mut self) -> Option<char>{
         let r = self.next();
         self.backup();
-        return r;
+        r
     }
 
     fn backup(&mut self){
-        self.pos = self.pos - self.width; 
+        self.pos -= self.width; 
+    }
+
+    // 判断当前位置是不是右分隔符，第二个值表示右分隔符前面是否带有 trim 标记
+    fn at_right_delim(&self) -> (bool, bool){
+        let rest = &self.input[self.pos..];
+        if has_right_trim_marker(rest) && rest[TRIMMARKERLEN..].starts_with(self.right_delim){
+            return (true, true);
+        }
+        if rest.starts_with(self.right_delim){
+            return (true, false);
+        }
+        (false, false)
     }
 
     fn at_terminator(&mut self)->bool{
         let r = self.peek();
-        if r == None{return true};
+        if r.is_none(){return true};
         match r{
-            Some(c) if is_space(c) || is_end_of_line(c)=>return true,
-            Some(c) if c == '.' || c==',' || c=='|' || c==':' || c==')' || c=='(' => return true,
-            Some(c) if self.right_delim.starts_with(c) => return true,
-            _ => return false,
+            Some(c) if is_space(c) || is_end_of_line(c)=>true,
+            Some(c) if c == '.' || c==',' || c=='|' || c==':' || c==')' || c=='(' => true,
+            Some(c) if self.right_delim.starts_with(c) => true,
+            _ => false,
         }
     }
 
     fn scan_number(&mut self) -> bool{
+        // 只负责找出数字的边界，像 1__2 这样的写法要到解析数值时才会报错
         self.accept("+-"); //正负号（也有可能不存在)
-        let mut digits = "0123456789";
-        if self.accept("0") && self.accept("xX"){
-            digits = "0123456789abcdefABCDEF"; // 16 进制
+        let mut digits = DECIMALDIGITS;
+        if self.accept("0"){
+            // 0 开头不代表浮点数是八进制的
+            if self.accept("xX"){
+                digits = HEXDIGITS; // 16 进制
+            }else if self.accept("oO"){
+                digits = OCTALDIGITS; // 8 进制
+            }else if self.accept("bB"){
+                digits = BINARYDIGITS; // 2 进制
+            }
         }
         self.accept_run(digits);
         if self.accept("."){
             self.accept_run(digits); // 小数点处理
         }
-        if self.accept("eE"){
+        if digits == DECIMALDIGITS && self.accept("eE"){
             // 科学计数法处理
             self.accept("+-");
-            self.accept_run("0123456789");
+            self.accept_run(DECIMALDIGITS);
+        }
+        if digits == HEXDIGITS && self.accept("pP"){
+            // 16 进制浮点数的指数部分，如 0x1p-2
+            self.accept("+-");
+            self.accept_run(DECIMALDIGITS);
         }
         self.accept("i"); // 复数处理
         if is_alphanumeric(self.peek()){
             self.next();
             return false;
         }
-        return true;
+        true
     }
 
     fn accept(&mut self, valid: &str) -> bool{
@@ -198,15 +317,15 @@ impl Lexer{
         match self.next(){
             None => {
                 self.backup();
-                return false;
+                false
             },
             Some(r) =>{
                 match valid.find(r){
                     None => {
                         self.backup();
-                        return false;
+                        false
                     },
-                    _ => return true,
+                    _ => true,
                 }
             }
         }
@@ -218,9 +337,8 @@ impl Lexer{
             match self.next(){
                 None => break,
                 Some(r) =>{
-                    match valid.find(r){
-                        None =>break,
-                        _ => (),
+                    if valid.find(r).is_none(){
+                        break;
                     }
                 }
             }
@@ -228,377 +346,565 @@ impl Lexer{
         self.backup();
     }
 
-    fn line_number(&mut self) -> usize{
-        let mt:Vec<&str> = self.input[0..self.last_pos].matches("\n").collect();
-        let length: usize = mt.len();
-        return 1 + length;
-    }
-
-    pub fn next_item(&mut self) -> Option<Rc<Item>>{
-        let item = self.items.next();
-        match item{
-            None => None,
-            Some(em) => {
-                self.last_pos = em.pos;
-                return Some(em.clone());
+    // 计算 pos 处的行号和列号。item 的起始位置是递增的，
+    // 所以每次只需要扫描上一次之后的部分，整个模板只会被扫描一遍
+    fn line_col(&mut self, pos: Pos) -> (usize, usize){
+        for c in self.input[self.line_pos..pos].chars(){
+            if c == '\n'{
+                self.line += 1;
+                self.col = 1;
+            }else{
+                self.col += 1;
             }
         }
+        self.line_pos = pos;
+        (self.line, self.col)
     }
 
-    fn drain(&mut self){
+    pub fn next_item(&mut self) -> Option<Item<'a>>{
+        // 缓存里没有 item 时才往前执行状态机，直到产生新的 item 或者结束
         loop{
-            match self.items.next(){
-                None => break,
-                _ => (),
+            if let Some(item) = self.items.next(){
+                self.last_pos = item.pos;
+                return Some(item);
+            }
+            match self.state.take(){
+                None => return None,
+                Some(state) => {
+                    self.state = state.scan(self);
+                    if self.state.is_none() && self.items.last_is_error(){
+                        if self.recover{
+                            self.resync();
+                            self.state = Some(State::Text);
+                        }else if self.lossless{
+                            // 不再继续扫描，但出错位置之后的源码也不能丢
+                            self.pos = self.input.len();
+                            self.ignore();
+                        }
+                    }
+                }
             }
-        } 
+        }
     }
 
-    pub fn run(&mut self){
-        // mem::replace(&mut self.state, Box::new(StateText));
-        let mut state:Box<StateFn>;
-        state = Box::new(StateText);
-        loop{
-            let st = state.scan(self);
-            match st{
-                None => break,
-                Some(statebox) =>{
-                    state = statebox;
-                } 
-            }
-        } 
+    // 出错后跳到下一个右分隔符或者换行之后（哪个先出现就用哪个），
+    // 然后当作模板文本继续扫描；都找不到的话直接跳到结尾
+    fn resync(&mut self){
+        let rest = &self.input[self.start..];
+        let delim = rest.find(self.right_delim);
+        let newline = rest.find('\n');
+        let skip = match (delim, newline){
+            (Some(d), Some(n)) if n < d => n + 1,
+            (Some(d), _) => d + self.right_delim.len(),
+            (None, Some(n)) => n + 1,
+            (None, None) => rest.len(),
+        };
+        self.pos = self.start + skip;
+        self.ignore();
+        self.paren_depth = 0;
     }
 }
 
-pub fn lex(name: &'static str, input: &'static str, left: &'static str, right : &'static str) -> Lexer{
-    let mut leftdelim = left;
-    if left == ""{
-        leftdelim = LEFTDELIM;
-    }
-    let mut rightdelim = right;
-    if right == ""{
-        rightdelim = RIGHTDELIM;
+impl<'a> Iterator for Lexer<'a>{
+    type Item = Item<'a>;
+
+    fn next(&mut self) -> Option<Item<'a>>{
+        self.next_item()
     }
-    let items: Vec<Rc<Item>> = Vec::new();
-    let ch = Channel{
-        index: 0,
-        items: items
-    };
-    let l = Lexer{
-        name: name,
-        input: input,
-        left_delim: leftdelim,
-        right_delim: rightdelim,
-        start: 0,
-        pos: 0,
-        width: 0,
-        last_pos: 0,
-        paren_depth: 0,
-        items: ch
-    };
-    return l;
 }
 
-trait StateFn{
-    fn scan(&self, l: &mut Lexer) -> Option<Box<StateFn>>;
+// 词法分析器的配置，默认值和 Go 的 text/template 一致。
+// 可以用同一份配置扫描多个模板:
+//     let options = LexerOptions::new().delims("[[", "]]").allow_newlines(false);
+//     let l = options.lex("name", input);
+#[derive(Debug, Clone)]
+// 字段都只能用下面的方法设置，这样 delims 和 comments 把空字符串换成默认值的处理不会被绕过
+pub struct LexerOptions<'a>{
+    left_delim: &'a str,
+    right_delim: &'a str,
+    left_comment: &'a str,
+    right_comment: &'a str,
+    allow_newlines: bool,
+    emit_comments: bool,
+    lossless: bool,
+    recover: bool,
+    // 只能是 EXTENSIONKEYWORDS 里的关键字，用 keyword 设置
+    disabled_keywords: Vec<ItemType>,
 }
 
-struct StateText;
-impl StateFn for StateText{
-    fn scan(&self, l: &mut Lexer) -> Option<Box<StateFn>>{
-        let length = l.input.len();
-        loop{
-            if l.input[l.pos..length].starts_with(l.left_delim){
-                if l.pos > l.start{
-                    l.emit(ItemType::ItemText);
-                }
-                // lex_left_delim(l);
-                return Some(Box::new(StateLeftDelim));
-            }
-            match l.next(){
-                None => break,
-                _ => continue,
-            }
+// 后来才加入 Go 的关键字，可以用 LexerOptions::keyword 关闭，
+// 关闭后当作普通的 identifier。if、end、define 这些核心关键字不能关闭
+pub const EXTENSIONKEYWORDS: &[ItemType] = &[ItemType::ItemBlock, ItemType::ItemBreak, ItemType::ItemContinue];
+
+impl<'a> LexerOptions<'a>{
+    pub fn new() -> LexerOptions<'a>{
+        LexerOptions{
+            left_delim: LEFTDELIM,
+            right_delim: RIGHTDELIM,
+            left_comment: LEFTCOMMENT,
+            right_comment: RIGHTCOMMENT,
+            allow_newlines: true,
+            emit_comments: false,
+            lossless: false,
+            recover: false,
+            disabled_keywords: vec![],
         }
-        if l.pos > l.start{
-            l.emit(ItemType::ItemText);
+    }
+
+    // 只认识 Go 1.0 的关键字，block、break、continue 都是普通的 identifier，
+    // 其余配置和 new 相同
+    pub fn strict() -> LexerOptions<'a>{
+        let mut options = LexerOptions::new();
+        for k in EXTENSIONKEYWORDS{
+            options = options.keyword(*k, false);
+        }
+        options
+    }
+
+    // 空字符串表示使用默认的 {{ 和 }}
+    pub fn delims(mut self, left: &'a str, right: &'a str) -> LexerOptions<'a>{
+        self.left_delim = if left.is_empty(){ LEFTDELIM }else{ left };
+        self.right_delim = if right.is_empty(){ RIGHTDELIM }else{ right };
+        self
+    }
+
+    // 空字符串表示使用默认的 /* 和 */
+    pub fn comments(mut self, left: &'a str, right: &'a str) -> LexerOptions<'a>{
+        self.left_comment = if left.is_empty(){ LEFTCOMMENT }else{ left };
+        self.right_comment = if right.is_empty(){ RIGHTCOMMENT }else{ right };
+        self
+    }
+
+    pub fn allow_newlines(mut self, allow: bool) -> LexerOptions<'a>{
+        self.allow_newlines = allow;
+        self
+    }
+
+    // 和 Go 的 parse.ParseComments 模式一样保留注释
+    pub fn emit_comments(mut self, emit: bool) -> LexerOptions<'a>{
+        self.emit_comments = emit;
+        self
+    }
+
+    // 给格式化、重构之类需要原样写回模板的工具使用，不依赖 recover，
+    // 出错时也能原样拼回输入
+    pub fn lossless(mut self, lossless: bool) -> LexerOptions<'a>{
+        self.lossless = lossless;
+        self
+    }
+
+    pub fn recover(mut self, recover: bool) -> LexerOptions<'a>{
+        self.recover = recover;
+        self
+    }
+
+    // 打开或关闭某个扩展关键字，比如为了兼容旧版本的 Go 模板关闭 break 和 continue。
+    // typ 不在 EXTENSIONKEYWORDS 里时 panic，关闭核心关键字会让解析器无法工作
+    pub fn keyword(mut self, typ: ItemType, enabled: bool) -> LexerOptions<'a>{
+        if !EXTENSIONKEYWORDS.contains(&typ){
+            panic!("{} is not an extension keyword", typ);
+        }
+        self.disabled_keywords.retain(|k| *k != typ);
+        if !enabled{
+            self.disabled_keywords.push(typ);
+        }
+        self
+    }
+
+    pub fn lex(&self, name: &'a str, input: &'a str) -> Lexer<'a>{
+        let ch = Channel{
+            items: VecDeque::new()
+        };
+        Lexer{
+            name,
+            input,
+            left_delim: self.left_delim,
+            right_delim: self.right_delim,
+            left_comment: self.left_comment,
+            right_comment: self.right_comment,
+            start: 0,
+            pos: 0,
+            width: 0,
+            last_pos: 0,
+            paren_depth: 0,
+            recover: self.recover,
+            allow_newlines: self.allow_newlines,
+            emit_comments: self.emit_comments,
+            lossless: self.lossless,
+            disabled_keywords: self.disabled_keywords.clone(),
+            items: ch,
+            line: 1,
+            col: 1,
+            line_pos: 0,
+            state: Some(State::Text)
         }
-        l.emit(ItemType::ItemEOF);
-        None
     }
 }
 
-struct StateLeftDelim;
-impl StateFn for StateLeftDelim{
-    fn scan(&self, l:&mut Lexer)->Option<Box<StateFn>>{
-        l.pos = l.pos + l.left_delim.len();
-        let length = l.input.len();
-        if l.input[l.pos..length].starts_with(LEFTCOMMENT){
-            return Some(Box::new(StateComment));
+impl<'a> Default for LexerOptions<'a>{
+    fn default() -> LexerOptions<'a>{
+        LexerOptions::new()
+    }
+}
+
+// 模板内容通常来自文件或网络，所以这里借用调用者的字符串，而不是要求 'static
+pub fn lex<'a>(name: &'a str, input: &'a str, left: &'a str, right: &'a str) -> Lexer<'a>{
+    LexerOptions::new().delims(left, right).lex(name, input)
+}
+
+// 状态机的状态。每个状态对应一个 state_xxx 函数，返回下一个状态，
+// 状态本身不带数据，切换状态时不需要分配内存
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State{
+    Text,
+    LeftDelim,
+    RightDelim,
+    Comment,
+    Space,
+    InsideAction,
+    Quote,
+    RawQuote,
+    Variable,
+    Char,
+    Field,
+    Number,
+    Identifier,
+}
+
+impl State{
+    fn scan(self, l: &mut Lexer) -> Option<State>{
+        match self{
+            State::Text => state_text(l),
+            State::LeftDelim => state_left_delim(l),
+            State::RightDelim => state_right_delim(l),
+            State::Comment => state_comment(l),
+            State::Space => state_space(l),
+            State::InsideAction => state_inside_action(l),
+            State::Quote => state_quote(l),
+            State::RawQuote => state_raw_quote(l),
+            State::Variable => state_variable(l),
+            State::Char => state_char(l),
+            State::Field => state_field(l),
+            State::Number => state_number(l),
+            State::Identifier => state_identifier(l),
         }
-        l.emit(ItemType::ItemLeftDelim);
-        l.paren_depth = 0;
-        return Some(Box::new(StateInsideAction));
     }
 }
 
-struct StateRightDelim;
-impl StateFn for StateRightDelim{
-    fn scan(&self, l:&mut Lexer)->Option<Box<StateFn>>{
-        l.pos = l.pos + l.right_delim.len();
-        l.emit(ItemType::ItemRightDelim);
-        return Some(Box::new(StateText));
+fn state_text(l: &mut Lexer) -> Option<State>{
+    let length = l.input.len();
+    // 文本里只需要找下一个左分隔符，不用逐个字符解码
+    match l.input[l.pos..length].find(l.left_delim){
+        Some(x) => {
+            l.pos += x;
+            // {{- 要去掉前面文本末尾的空白
+            let mut trim_length = 0;
+            let delim_end = l.pos + l.left_delim.len();
+            if has_left_trim_marker(&l.input[delim_end..length]){
+                trim_length = right_trim_length(&l.input[l.start..l.pos]);
+            }
+            l.pos -= trim_length;
+            if l.pos > l.start{
+                l.emit(ItemType::ItemText);
+            }
+            l.pos += trim_length;
+            l.ignore();
+            return Some(State::LeftDelim);
+        },
+        None => l.pos = length,
     }
+    if l.pos > l.start{
+        l.emit(ItemType::ItemText);
+    }
+    l.emit(ItemType::ItemEOF);
+    None
 }
 
-struct StateComment;
-impl StateFn for StateComment{
-    fn scan(&self, l:&mut Lexer)->Option<Box<StateFn>>{
-        // 不知道为什么Go要求模板注释必须紧贴着delim {{/* */ }} 这样多一个空格都是违法的 
-        l.pos = l.pos + LEFTCOMMENT.len();
+fn state_left_delim(l: &mut Lexer) -> Option<State>{
+    l.pos += l.left_delim.len();
+    let length = l.input.len();
+    let mut after_marker = 0;
+    if has_left_trim_marker(&l.input[l.pos..length]){
+        after_marker = TRIMMARKERLEN;
+    }
+    if l.input[l.pos+after_marker..length].starts_with(l.left_comment){
+        l.pos += after_marker;
+        l.ignore();
+        return Some(State::Comment);
+    }
+    // 发出的 ItemLeftDelim 不包含 trim 标记
+    l.emit(ItemType::ItemLeftDelim);
+    l.pos += after_marker;
+    l.ignore();
+    l.paren_depth = 0;
+    Some(State::InsideAction)
+}
+
+fn state_right_delim(l: &mut Lexer) -> Option<State>{
+    let (_, trim_space) = l.at_right_delim();
+    if trim_space{
+        l.pos += TRIMMARKERLEN;
+        l.ignore();
+    }
+    l.pos += l.right_delim.len();
+    l.emit(ItemType::ItemRightDelim);
+    if trim_space{
+        // -}} 要去掉后面文本开头的空白
         let length = l.input.len();
-        match l.input[l.pos..length].find(RIGHTCOMMENT){
-            None => {
-                let error = String::from("unclosed comment");
+        l.pos = l.pos + left_trim_length(&l.input[l.pos..length]);
+        l.ignore();
+    }
+    Some(State::Text)
+}
+
+fn state_comment(l: &mut Lexer) -> Option<State>{
+    // 不知道为什么Go要求模板注释必须紧贴着delim {{/* */ }} 这样多一个空格都是违法的 
+    // 唯一允许的是 trim 标记: {{- /* */ -}}
+    l.pos += l.left_comment.len();
+    let length = l.input.len();
+    match l.input[l.pos..length].find(l.right_comment){
+        None => {
+            let error = String::from("unclosed comment");
+            l.errorf(error);
+            // l.errorf("unclosed comment");
+            None
+        },
+        Some(i) => {
+            l.pos = l.pos + i + l.right_comment.len();
+            let (delim, trim_space) = l.at_right_delim();
+            if !delim{
+                let error = String::from("comment ends before closing delimiter");
                 l.errorf(error);
-                // l.errorf("unclosed comment");
                 return None;
-            },
-            Some(i) => {
-                l.pos = l.pos + i + RIGHTCOMMENT.len();
-                if !l.input[l.pos..length].starts_with(RIGHTDELIM){
-                    let error = String::from("comment ends before closing delimiter");
-                    l.errorf(error);
-                    return None;
-                }
-                l.pos = l.pos + RIGHTDELIM.len();
-                l.ignore();
-                return Some(Box::new(StateText));
             }
+            if l.emit_comments{
+                // 注释的内容包括注释符号本身，如 "/* hello */"
+                l.emit(ItemType::ItemComment);
+            }
+            if trim_space{
+                l.pos += TRIMMARKERLEN;
+            }
+            l.pos += l.right_delim.len();
+            if trim_space{
+                l.pos = l.pos + left_trim_length(&l.input[l.pos..length]);
+            }
+            l.ignore();
+            Some(State::Text)
         }
     }
 }
 
-struct StateSpace;
-impl StateFn for StateSpace{
-    fn scan(&self, l:&mut Lexer)->Option<Box<StateFn>>{
-        loop{
-            match l.peek(){
-                Some(r) => {
-                    if is_space(r){
-                        l.next();
-                    }else{
-                        break;
-                    }
-                },
-                _ => {
-                    break;
-                }
+fn state_space(l: &mut Lexer) -> Option<State>{
+    let mut num_spaces = 0;
+    loop{
+        match l.peek(){
+            Some(r) if is_space(r) => {
+                l.next();
+                num_spaces += 1;
+            },
+            _ => {
+                break;
             }
         }
-        l.emit(ItemType::ItemSpace);
-        return Some(Box::new(StateInsideAction));
     }
+    // 空白后面可能紧跟着 "-}}"，最后一个空格属于右分隔符的 trim 标记
+    let length = l.input.len();
+    if has_right_trim_marker(&l.input[l.pos-1..length]) && l.input[l.pos-1+TRIMMARKERLEN..length].starts_with(l.right_delim){
+        l.pos -= 1;
+        if num_spaces == 1{
+            return Some(State::RightDelim);
+        }
+    }
+    l.emit(ItemType::ItemSpace);
+    Some(State::InsideAction)
 }
 
-struct StateInsideAction;
-impl StateFn for StateInsideAction{
-    // InsideAction 类似于 {{ $x =1 }} 中间那部分的处理
-    fn scan(&self, l:&mut Lexer)->Option<Box<StateFn>>{
-        let length = l.input.len();
-        if l.input[l.pos..length].starts_with(l.right_delim){
-            if l.paren_depth == 0{
-                return Some(Box::new(StateRightDelim));
-            }
-            // 括号未闭合
-            let error = String::from("unclosed left paren");
-            l.errorf(error);
-            return None;
+// InsideAction 类似于 {{ $x =1 }} 中间那部分的处理
+fn state_inside_action(l: &mut Lexer) -> Option<State>{
+    let (delim, _) = l.at_right_delim();
+    if delim{
+        if l.paren_depth == 0{
+            return Some(State::RightDelim);
         }
-        let next =  l.next();
-        if next == None{
+        // 括号未闭合
+        let error = String::from("unclosed left paren");
+        l.errorf(error);
+        return None;
+    }
+    let next =  l.next();
+    if next.is_none(){
+        let error = String::from("unclosed action");
+        l.errorf(error);
+        return None;
+    }
+    match next{
+        Some(r) if is_end_of_line(r) && !l.allow_newlines => {
             let error = String::from("unclosed action");
             l.errorf(error);
             return None;
-        }
-        println!("{:?}", next);
-        match next{
-            Some(r) if is_end_of_line(r) => {
-                let error = String::from("unclosed action");
+        },
+        // 和 Go 1.16 以后一样，action 里的换行和空格一样处理，可以把长的 pipeline 分成多行
+        Some(r) if is_space(r) => {
+            // 把空格退回去，StateSpace 需要检查 " -}}" 这种情况
+            l.backup();
+            return Some(State::Space);
+        },
+        Some('=') => l.emit(ItemType::ItemAssign),
+        Some(':') => {
+            // 只有 := 是合法的，单独的 : 或者已经到结尾都要报错
+            if l.next() != Some('='){
+                let error = String::from("expected :=");
                 l.errorf(error);
                 return None;
-            },
-            Some(r) if is_space(r) => return Some(Box::new(StateSpace)),
-            Some(r) if r == ':' => {
-                if l.next().unwrap() != '='{
-                    let error = String::from("expected :=");
-                    l.errorf(error);
-                    return None;
-                }
-                l.emit(ItemType::ItemColonEquals);
-            },
-            Some(r) if r == '|' => l.emit(ItemType::ItemPipe),
-            Some(r) if r == '"' => return Some(Box::new(StateQuote)),
-            Some(r) if r == '`' => return Some(Box::new(StateRawQuote)),
-            Some(r) if r == '$' => return Some(Box::new(StateVariable)),
-            Some(r) if r == '\'' => return Some(Box::new(StateChar)),
-            Some(r) if r == '.' => {
-                if l.pos < l.input.len(){
-                    let r1 = &l.input[l.pos..l.pos+1];
-                    let r2: Vec<char> = r1.chars().collect();
-                    let r3 = r2[0];
-                    if r3 < '0' || '9' < r3{
-                        return Some(Box::new(StateField));
-                    }   
-                }
-                l.backup();
-                return Some(Box::new(StateNumber));
-            },
-            Some(r) if r == '+' || r =='-' ||('0' <= r && r <= '9')=>{
-                l.backup();
-                return Some(Box::new(StateNumber));
-            },
-            Some(r) if is_alphanumeric(Some(r)) =>{
-                l.backup();
-                return Some(Box::new(StateIdentifier));
-            },
-            Some(r) if r == '(' => {
-                l.emit(ItemType::ItemLeftParen);
-                l.paren_depth = l.paren_depth + 1;
-            },
-            Some(r) if r == ')' => {
-                l.emit(ItemType::ItemRightParen);
-                l.paren_depth = l.paren_depth - 1;
-                if l.paren_depth < 0{
-                    let error = String::from("unexpected right paren ')'");
-                    l.errorf(error);
-                    return None;
-                }
-            },
-            // \u007F 为最大的ASCII值， 此处还缺少 isPrintable 的判断
-            Some(r) if r < '\u{007F}' && is_print(r) =>{
-                println!("her===");
-                l.emit(ItemType::ItemChar);
-                return Some(Box::new(StateInsideAction));
-            },
-            Some(r) => {
-                let error = format!("unrecognized character in action: {}", r);
+            }
+            l.emit(ItemType::ItemColonEquals);
+        },
+        Some('|') => l.emit(ItemType::ItemPipe),
+        Some('"') => return Some(State::Quote),
+        Some('`') => return Some(State::RawQuote),
+        Some('$') => return Some(State::Variable),
+        Some('\'') => return Some(State::Char),
+        Some('.') => {
+            // 只看下一个字节就够了，不用解码，也不会破坏 backup
+            if l.pos < l.input.len(){
+                let r = l.input.as_bytes()[l.pos];
+                if !r.is_ascii_digit(){
+                    return Some(State::Field);
+                }   
+            }
+            l.backup();
+            return Some(State::Number);
+        },
+        Some(r) if r == '+' || r =='-' || r.is_ascii_digit() =>{
+            l.backup();
+            return Some(State::Number);
+        },
+        Some(r) if is_alphanumeric(Some(r)) =>{
+            l.backup();
+            return Some(State::Identifier);
+        },
+        Some('(') => {
+            l.emit(ItemType::ItemLeftParen);
+            l.paren_depth += 1;
+        },
+        Some(')') => {
+            l.emit(ItemType::ItemRightParen);
+            l.paren_depth -= 1;
+            if l.paren_depth < 0{
+                let error = String::from("unexpected right paren ')'");
                 l.errorf(error);
                 return None;
             }
-            _ => return None,
+        },
+        // \u007F 为最大的ASCII值，非 ASCII 的标点符号在 action 里都是非法的
+        Some(r) if r <= '\u{007F}' && is_print(r) =>{
+            l.emit(ItemType::ItemChar);
+            return Some(State::InsideAction);
+        },
+        Some(r) => {
+            let error = format!("unrecognized character in action: {}", format_rune(r));
+            l.errorf(error);
+            return None;
         }
-        Some(Box::new(StateInsideAction))
+        _ => return None,
     }
+    Some(State::InsideAction)
 }
 
-struct StateQuote;
-impl StateFn for StateQuote{
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        loop{
-            let next = l.next();
-            if next == None{
-                let error = format!("{}", "unterminated quoted string");
+fn state_quote(l: &mut Lexer) -> Option<State>{
+    loop{
+        let next = l.next();
+        if next.is_none(){
+            let error = String::from("unterminated quoted string");
+            l.errorf(error);
+            return None;
+        }
+        match next.unwrap(){
+            // \"abc  应对这种情况,当成普通字符处理
+            '\\' => {
+                let r = l.next();
+                if r.is_some() && r.unwrap() != '\n'{
+                    continue;
+                }
+                let error = String::from("unterminated quoted string");
+                l.errorf(error);
+                return None;
+            },
+            '\n' => {
+                let error = String::from("unterminated quoted string");
                 l.errorf(error);
                 return None;
             }
-            match next.unwrap(){
-                // \"abc  应对这种情况,当成普通字符处理
-                '\\' => {
-                    let r = l.next();
-                    if r != None && r.unwrap() != '\n'{
-                        continue;
-                    }
-                    let error = format!("{}", "unterminated quoted string");
-                    l.errorf(error);
-                    return None;
-                },
-                '\n' => {
-                    let error = format!("{}", "unterminated quoted string");
-                    l.errorf(error);
-                    return None;
-                }
-                '"' => break,
-                _ => ()
-            }
-
+            '"' => break,
+            _ => ()
         }
-        l.emit(ItemType::ItemString);
-        return Some(Box::new(StateInsideAction));
+
     }
+    l.emit(ItemType::ItemString);
+    Some(State::InsideAction)
 }
 
-struct StateRawQuote;
-impl StateFn for StateRawQuote{
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        loop{
-            let next = l.next();
-            if next == None{
-                let error = format!("{}", "unterminated raw quoted string");
-                l.errorf(error);
-                return None;
-            }
-            if next.unwrap() == '`'{
-                break;
-            }
+fn state_raw_quote(l: &mut Lexer) -> Option<State>{
+    loop{
+        let next = l.next();
+        if next.is_none(){
+            let error = String::from("unterminated raw quoted string");
+            l.errorf(error);
+            return None;
+        }
+        if next.unwrap() == '`'{
+            break;
         }
-        l.emit(ItemType::ItemRawString);
-        return Some(Box::new(StateInsideAction));
     }
+    l.emit(ItemType::ItemRawString);
+    Some(State::InsideAction)
 }
 
-struct StateVariable;
-impl StateFn for StateVariable{
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        // if l.at_terminator(){
-        //     l.emit(ItemType::ItemVariable);
-        //     return Some(Box::new(StateInsideAction));
-        // }
-        return state_field_or_variable(l, ItemType::ItemVariable);
-    }
+fn state_variable(l: &mut Lexer) -> Option<State>{
+    // if l.at_terminator(){
+    //     l.emit(ItemType::ItemVariable);
+    //     return Some(State::InsideAction);
+    // }
+    state_field_or_variable(l, ItemType::ItemVariable)
 }
-struct StateChar;
-impl StateFn for StateChar{
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        loop{
-            let next = l.next();
-            if next == None{
-                let error = format!("{}", "unterminated character constant");
+
+fn state_char(l: &mut Lexer) -> Option<State>{
+    loop{
+        let next = l.next();
+        if next.is_none(){
+            let error = String::from("unterminated character constant");
+            l.errorf(error);
+            return None;
+        }
+        match next.unwrap(){
+            '\\' =>{
+                let r = l.next();
+                if r.is_some() && r.unwrap() != '\n'{
+                    continue;
+                }
+                let error = String::from("unterminated character constant");
                 l.errorf(error);
                 return None;
-            }
-            match next.unwrap(){
-                '\\' =>{
-                    let r = l.next();
-                    if r != None && r.unwrap() != '\n'{
-                        continue;
-                    }
-                    let error = format!("{}", "unterminated character constant");
-                    l.errorf(error);
-                    return None;
-                },
-                '\n' =>{
-                    let error = format!("{}", "unterminated character constant");
-                    l.errorf(error);
-                    return None;
-                },
-                '\'' =>break,
-                _ => (),
-            }
+            },
+            '\n' =>{
+                let error = String::from("unterminated character constant");
+                l.errorf(error);
+                return None;
+            },
+            '\'' =>break,
+            _ => (),
         }
-        l.emit(ItemType::ItemCharConstant);
-        return Some(Box::new(StateInsideAction));
     }
+    l.emit(ItemType::ItemCharConstant);
+    Some(State::InsideAction)
 }
-struct StateField;
-impl StateFn for StateField{
-    // .x 这样的字段， . 已经扫描了
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        state_field_or_variable(l, ItemType::ItemField) 
-    }
+
+// .x 这样的字段， . 已经扫描了
+fn state_field(l: &mut Lexer) -> Option<State>{
+    state_field_or_variable(l, ItemType::ItemField) 
 }
 
-fn state_field_or_variable(l: &mut Lexer, typ: ItemType) -> Option<Box<StateFn>>{
+fn state_field_or_variable(l: &mut Lexer, typ: ItemType) -> Option<State>{
     if l.at_terminator(){
         // 位于 "." 或者 "$" 之后的是终结符, 比如 .|pipe 这种，算作 ItemDot
         if typ == ItemType::ItemVariable{
@@ -606,14 +912,14 @@ fn state_field_or_variable(l: &mut Lexer, typ: ItemType) -> Option<Box<StateFn>>
         }else{
             l.emit(ItemType::ItemDot);
         }
-        return Some(Box::new(StateInsideAction));
+        return Some(State::InsideAction);
     }
     loop{
         let r = l.next();
         if !is_alphanumeric(r){
             l.backup();
             if !l.at_terminator(){
-                let error = format!("bad character {}", r.unwrap());
+                let error = format!("bad character {}", format_rune(r.unwrap()));
                 l.errorf(error);
                 return None;
             }
@@ -621,83 +927,82 @@ fn state_field_or_variable(l: &mut Lexer, typ: ItemType) -> Option<Box<StateFn>>
         }
     }
     l.emit(typ);
-    return Some(Box::new(StateInsideAction));
+    Some(State::InsideAction)
 }
 
-struct StateNumber;
-impl StateFn for StateNumber{
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        if !l.scan_number(){
-            let error = format!(r#"bad number syntax: "{}""#, &l.input[l.start..l.pos]);
-            l.errorf(error);
-            return None;
-        }
-        // let sign = l.peek().unwrap();
-        match l.peek(){
-            None => l.emit(ItemType::ItemNumber),
-            Some(sign) => {
-                if sign == '+' || sign == '-'{
-                    // 复数，目前貌似不支持加法
-                    if !l.scan_number() || !l.input[l.pos-1..l.pos].starts_with('i'){
-                        let error = format!(r#"bad number syntax: "{}""#, &l.input[l.start..l.pos]);
-                        l.errorf(error);
-                        return None;
-                    }
-                    l.emit(ItemType::ItemComplex);
-                }else{
-                    l.emit(ItemType::ItemNumber);
+fn state_number(l: &mut Lexer) -> Option<State>{
+    if !l.scan_number(){
+        let error = format!("bad number syntax: {}", quote(&l.input[l.start..l.pos]));
+        l.errorf(error);
+        return None;
+    }
+    // let sign = l.peek().unwrap();
+    match l.peek(){
+        None => l.emit(ItemType::ItemNumber),
+        Some(sign) => {
+            if sign == '+' || sign == '-'{
+                // 复数，目前貌似不支持加法
+                if !l.scan_number() || !l.input[l.pos-1..l.pos].starts_with('i'){
+                    let error = format!("bad number syntax: {}", quote(&l.input[l.start..l.pos]));
+                    l.errorf(error);
+                    return None;
                 }
+                l.emit(ItemType::ItemComplex);
+            }else{
+                l.emit(ItemType::ItemNumber);
             }
         }
-        return Some(Box::new(StateInsideAction));
     }
+    Some(State::InsideAction)
 }
-struct StateIdentifier;
-impl StateFn for StateIdentifier{
-    // 主要用于识别几种类型：布尔值，关键字，以 . 开头的字段， 不以 . 开头的字段
-    // 两者分别如 .x 以及 x (可能是数字一类的)
-    fn scan(&self, l:&mut Lexer) -> Option<Box<StateFn>>{
-        loop{
-            let next = l.next();
-            if !is_alphanumeric(next){
-                // 一般情况下是遇到空格或者. 号才执行如下代码
-                l.backup();
-                let word = &l.input[l.start..l.pos];
-                // identifier 后面必须有合法的字符，.x x 是合法的，但是 .x=3 这样就是违法的
-                // 所以这里必须对identifier后面的字符进行判断
-                if !l.at_terminator(){
-                    let error = format!("bad character {}", next.unwrap());
-                    l.errorf(error);
-                    return None;
-                }
-                if word.starts_with("."){
-                    l.emit(ItemType::ItemField);
-                    break;
-                }
-                if word == "true" || word == "false"{
-                    l.emit(ItemType::ItemBool);
-                    break;
-                }
-                let key = is_keyword(word);
-                match key{
-                    None => l.emit(ItemType::ItemIdentifier),
-                    Some(k) => {
-                        if k > ItemType::ItemKeyword{
-                            l.emit(k);
-                        }else{
-                            l.emit(ItemType::ItemIdentifier);
-                        }
+
+// 主要用于识别几种类型：布尔值，关键字，以 . 开头的字段， 不以 . 开头的字段
+// 两者分别如 .x 以及 x (可能是数字一类的)
+fn state_identifier(l: &mut Lexer) -> Option<State>{
+    loop{
+        let next = l.next();
+        if !is_alphanumeric(next){
+            // 一般情况下是遇到空格或者. 号才执行如下代码
+            l.backup();
+            let word = &l.input[l.start..l.pos];
+            // identifier 后面必须有合法的字符，.x x 是合法的，但是 .x=3 这样就是违法的
+            // 所以这里必须对identifier后面的字符进行判断
+            if !l.at_terminator(){
+                let error = format!("bad character {}", format_rune(next.unwrap()));
+                l.errorf(error);
+                return None;
+            }
+            if word.starts_with("."){
+                l.emit(ItemType::ItemField);
+                break;
+            }
+            if word == "true" || word == "false"{
+                l.emit(ItemType::ItemBool);
+                break;
+            }
+            let key = is_keyword(word);
+            match key{
+                None => l.emit(ItemType::ItemIdentifier),
+                Some(k) => {
+                    if !(EXTENSIONKEYWORDS.contains(&k) && l.disabled_keywords.contains(&k)){
+                        l.emit(k);
+                    }else{
+                        l.emit(ItemType::ItemIdentifier);
                     }
                 }
-                break;
             }
+            break;
         }
-        return Some(Box::new(StateInsideAction));
     }
+    Some(State::InsideAction)
 }
+
 fn is_keyword(key: &str) -> Option<ItemType>{
     match key{
         "." => Some(ItemType::ItemDot),
+        "block" => Some(ItemType::ItemBlock),
+        "break" => Some(ItemType::ItemBreak),
+        "continue" => Some(ItemType::ItemContinue),
         "define" => Some(ItemType::ItemDefine),
         "else" => Some(ItemType::ItemElse),
         "if" => Some(ItemType::ItemIf),
@@ -711,35 +1016,91 @@ fn is_keyword(key: &str) -> Option<ItemType>{
 }
 
 
-// 不给用 is_char_boundary，只能抄一份代码放这里了
-#[inline]
-pub fn is_char_boundary(input: &str, index: usize) -> bool{
-    if index == input.len(){
-        return true;
-    }
-    match input.as_bytes().get(index){
-        None => false,
-        Some(&b) => b < 128 || b >= 192,
+fn has_left_trim_marker(s: &str) -> bool{
+    let b = s.as_bytes();
+    b.len() >= 2 && b[0] == TRIMMARKER && is_space(b[1] as char)
+}
+
+fn has_right_trim_marker(s: &str) -> bool{
+    let b = s.as_bytes();
+    b.len() >= 2 && is_space(b[0] as char) && b[1] == TRIMMARKER
+}
+
+// 字符串末尾空白的长度
+fn right_trim_length(s: &str) -> Pos{
+    s.len() - s.trim_end_matches(SPACECHARS).len()
+}
+
+// 字符串开头空白的长度
+fn left_trim_length(s: &str) -> Pos{
+    s.len() - s.trim_start_matches(SPACECHARS).len()
+}
+
+// 以下几个字符分类函数都和 Go 的 text/template 保持一致
+pub fn is_space(input: char) -> bool{
+    input == ' '|| input == '\t' || input == '\r' || input == '\n'
+}
+
+// Go: r == '_' || unicode.IsLetter(r) || unicode.IsDigit(r)
+// Rust 的 char::is_alphanumeric 还包括 ²、Ⅻ 这类数字和一些组合符号，所以不能直接用
+pub fn is_alphanumeric(r: Option<char>) -> bool{
+    let c = match r{
+        Some(c) => c,
+        None => return false
+    };
+    // 模板里绝大多数字符都是 ASCII，不用查 Unicode 表
+    if c.is_ascii(){
+        return c == '_' || c.is_ascii_alphanumeric();
     }
+    matches!(get_general_category(c),
+        GeneralCategory::UppercaseLetter | GeneralCategory::LowercaseLetter | GeneralCategory::TitlecaseLetter |
+        GeneralCategory::ModifierLetter | GeneralCategory::OtherLetter | GeneralCategory::DecimalNumber)
 }
 
-fn is_space(input: char) -> bool{
-    return input == ' '|| input == '\t';
+pub fn is_end_of_line(r: char) -> bool{
+    r == '\r' || r == '\n'
 }
 
-fn is_alphanumeric(r: Option<char>) -> bool{
-    if r == None{
-        return false;
+pub fn is_print(r: char) -> bool{
+    // 模拟Golang 的unicode.IsPrint: 字母、组合符号、数字、标点、符号以及 ASCII 空格
+    if r.is_ascii(){
+        return (' '..='~').contains(&r);
     }
-    let c = r.unwrap();
-    return c == '_' || c.is_alphanumeric();
+    !matches!(get_general_category(r),
+        GeneralCategory::Control | GeneralCategory::Format | GeneralCategory::Surrogate |
+        GeneralCategory::PrivateUse | GeneralCategory::Unassigned | GeneralCategory::SpaceSeparator |
+        GeneralCategory::LineSeparator | GeneralCategory::ParagraphSeparator)
 }
 
-fn is_end_of_line(r: char) -> bool{
-    return r == '\r' || r == '\n';
+// 模拟 Go 的 %#U 格式，如 U+0001、U+00E9 'é'
+pub fn format_rune(r: char) -> String{
+    if is_print(r){
+        return format!("U+{:04X} '{}'", r as u32, r);
+    }
+    format!("U+{:04X}", r as u32)
 }
 
-fn is_print(r: char) -> bool{
-    // 模拟Golang 的unicode.IsPrint
-    return '\x20'  < r && r < '\x7e';
+// 模拟 Go 的 strconv.Quote (%q)，不可打印的字符用 \x、\u、\U 转义
+pub fn quote(s: &str) -> String{
+    let mut buf = String::with_capacity(s.len() + 2);
+    buf.push('"');
+    for r in s.chars(){
+        match r{
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\u{0007}' => buf.push_str("\\a"),
+            '\u{0008}' => buf.push_str("\\b"),
+            '\u{000C}' => buf.push_str("\\f"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            '\u{000B}' => buf.push_str("\\v"),
+            r if is_print(r) => buf.push(r),
+            r if r < ' ' || r == '\u{007F}' => buf.push_str(&format!("\\x{:02x}", r as u32)),
+            r if (r as u32) < 0x10000 => buf.push_str(&format!("\\u{:04x}", r as u32)),
+            r => buf.push_str(&format!("\\U{:08x}", r as u32)),
+        }
+    }
+    buf.push('"');
+    buf
 }
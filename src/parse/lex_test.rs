@@ -1,78 +1,36 @@
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::borrow::Cow;
 use super::lex::*;
-
-lazy_static!{
-    static ref ITEMNAME:HashMap<ItemType, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert(ItemType::ItemError, "error");
-        m.insert(ItemType::ItemBool, "bool");
-        m.insert(ItemType::ItemChar, "char");
-        m.insert(ItemType::ItemCharConstant, "charconst");
-        m.insert(ItemType::ItemComplex, "complex");
-        m.insert(ItemType::ItemColonEquals, ":=");
-        m.insert(ItemType::ItemEOF, "EOF");
-        m.insert(ItemType::ItemField, "field");
-        m.insert(ItemType::ItemIdentifier, "identifier");
-        m.insert(ItemType::ItemLeftDelim, "left delim");
-        m.insert(ItemType::ItemLeftParen, "(");
-        m.insert(ItemType::ItemNumber, "number");
-        m.insert(ItemType::ItemPipe, "pipe");
-        m.insert(ItemType::ItemRawString, "raw string");
-        m.insert(ItemType::ItemRightDelim, "right delim");
-        m.insert(ItemType::ItemRightParen, ")");
-        m.insert(ItemType::ItemSpace, "space");
-        m.insert(ItemType::ItemString, "string");
-        m.insert(ItemType::ItemVariable, "variable");
-        m.insert(ItemType::ItemDot, ".");
-        m.insert(ItemType::ItemDefine, "define");
-        m.insert(ItemType::ItemElse, "else");
-        m.insert(ItemType::ItemIf, "if");
-        m.insert(ItemType::ItemEnd, "end");
-        m.insert(ItemType::ItemNil, "nil");
-        m.insert(ItemType::ItemRange, "range");
-        m.insert(ItemType::ItemTemplate, "template");
-        m.insert(ItemType::ItemWith, "with");
-        m
-    };
-}
-
-impl ItemType{
-    fn to_string(&self) -> String{
-        let s = ITEMNAME.get(self);
-        match s{
-            None => format!("item {:?}", self),
-            Some(r) => format!("{}", r),
-        }
-    }
-}
-
+use super::node::Pos;
 
 struct LexTest{
     name: &'static str,
     input: &'static str,
-    items: Vec<Rc<Item>>
+    items: Vec<Item<'static>>
 }
 
 fn get_tests() -> Vec<LexTest>{
     let lextests:Vec<LexTest> = {
         let mut s:Vec<LexTest> = Vec::new();
-        let teof = Rc::new(Item{
+        let teof = Item{
             typ: ItemType::ItemEOF,
             pos: 0,
-            val: String::from("")
-        });
+            val: Cow::from(""),
+            line: 0,
+            col: 0
+        };
         let empty = LexTest{
             name: "empty",
             input: "",
             items: vec![teof.clone()]
         };
         s.push(empty);
-        let  tspace = Rc::new(Item{
+        let  tspace = Item{
             typ: ItemType::ItemSpace,
             pos: 0,
-            val: String::from(" ")
-        });
+            val: Cow::from(" "),
+            line: 0,
+            col: 0
+        };
         let tspace2 = item_factory(ItemType::ItemText, " \t\n");
         let spaces = LexTest{
             name: "spaces",
@@ -80,58 +38,74 @@ fn get_tests() -> Vec<LexTest>{
             items: vec![tspace2.clone(), teof.clone()]
         };
         s.push(spaces);
-        let  ttext = Rc::new(Item{
+        let  ttext = Item{
             typ: ItemType::ItemText,
             pos: 0,
-            val: String::from("now is the time")
-        });
+            val: Cow::from("now is the time"),
+            line: 0,
+            col: 0
+        };
         let text = LexTest{
             name: "text",
             input: "now is the time",
             items: vec![ttext.clone(), teof.clone()] 
         };
         s.push(text);
-        let  ttext2 = Rc::new(Item{
+        let  ttext2 = Item{
             typ: ItemType::ItemText,
             pos: 0,
-            val: String::from("hello-")
-        });
-        let  ttext3 = Rc::new(Item{
+            val: Cow::from("hello-"),
+            line: 0,
+            col: 0
+        };
+        let  ttext3 = Item{
             typ: ItemType::ItemText,
             pos: 0,
-            val: String::from("-world"),
-        });
+            val: Cow::from("-world"),
+            line: 0,
+            col: 0
+        };
         let textwithcomment = LexTest{
             name: "text with comment",
             input:"hello-{{/* this is a comment */}}-world",
             items: vec![ttext2.clone(), ttext3.clone(), teof.clone()],
         };
         s.push(textwithcomment);
-        let  tleft = Rc::new(Item{
+        let  tleft = Item{
             typ: ItemType::ItemLeftDelim,
             pos:0,
-            val: String::from("{{")
-        });
-        let  tchar1 = Rc::new(Item{
+            val: Cow::from("{{"),
+            line: 0,
+            col: 0
+        };
+        let  tchar1 = Item{
             typ: ItemType::ItemChar,
             pos: 0,
-            val: String::from(",")
-        });
-        let  tchar2 = Rc::new(Item{
+            val: Cow::from(","),
+            line: 0,
+            col: 0
+        };
+        let  tchar2 = Item{
             typ: ItemType::ItemChar,
             pos: 0,
-            val: String::from("@")
-        });
-        let  tchar3 = Rc::new(Item{
+            val: Cow::from("@"),
+            line: 0,
+            col: 0
+        };
+        let  tchar3 = Item{
             typ: ItemType::ItemChar,
             pos: 0,
-            val: String::from("%")
-        });
-        let  tright = Rc::new(Item{
+            val: Cow::from("%"),
+            line: 0,
+            col: 0
+        };
+        let  tright = Item{
             typ: ItemType::ItemRightDelim,
             pos: 0,
-            val: String::from("}}")
-        });
+            val: Cow::from("}}"),
+            line: 0,
+            col: 0
+        };
         let punctuation = LexTest{
             name: "punctuation",
             input: "{{,@% }}",
@@ -143,21 +117,27 @@ fn get_tests() -> Vec<LexTest>{
                         ]            
         };
         s.push(punctuation);
-        let  tlpar = Rc::new(Item{
+        let  tlpar = Item{
             typ: ItemType::ItemLeftParen,
             pos: 0,
-            val: String::from("(")
-        });
-        let  trpar = Rc::new(Item{
+            val: Cow::from("("),
+            line: 0,
+            col: 0
+        };
+        let  trpar = Item{
             typ: ItemType::ItemRightParen,
             pos: 0,
-            val: String::from(")")
-        });
-        let  tnumber3 = Rc::new(Item{
+            val: Cow::from(")"),
+            line: 0,
+            col: 0
+        };
+        let  tnumber3 = Item{
             typ: ItemType::ItemNumber,
             pos: 0,
-            val: String::from("3")
-        });
+            val: Cow::from("3"),
+            line: 0,
+            col: 0
+        };
         let parens = LexTest{
             name: "parens",
             input: "{{((3))}}",
@@ -174,22 +154,26 @@ fn get_tests() -> Vec<LexTest>{
             items:vec![tleft.clone(), tright.clone(), teof.clone()]
         };
         s.push(emptyaction);
-        let tfor = Rc::new(Item{
+        let tfor = Item{
             typ: ItemType::ItemIdentifier,
             pos: 0,
-            val: String::from("for")
-        });
+            val: Cow::from("for"),
+            line: 0,
+            col: 0
+        };
         let fortest = LexTest{
             name: "for",
             input: "{{for}}",
             items:vec![tleft.clone(), tfor.clone(), tright.clone(), teof.clone()]
         };
         s.push(fortest);
-        let tquote = Rc::new(Item{
+        let tquote = Item{
             typ: ItemType::ItemString,
             pos: 0,
-            val: String::from(r#""abc \n\t\" ""#)
-        });
+            val: Cow::from(r#""abc \n\t\" ""#),
+            line: 0,
+            col: 0
+        };
         let quote = LexTest{
             name: "quote",
             input: r#"{{"abc \n\t\" "}}"#,
@@ -197,22 +181,26 @@ fn get_tests() -> Vec<LexTest>{
         };
         s.push(quote);
         let raw = "`abc\n\t\" `";
-        let trawquote = Rc::new(Item{
+        let trawquote = Item{
             typ: ItemType::ItemRawString,
             pos: 0,
-            val: String::from(raw),
-        });
+            val: Cow::from(raw),
+            line: 0,
+            col: 0
+        };
         let rawquote = LexTest{
             name: "raw quote",
             input: "{{`abc\n\t\" `}}",
             items:vec![tleft.clone(), trawquote.clone(), tright.clone(), teof.clone()]
         };
         s.push(rawquote);
-        let trawquotenl = Rc::new(Item{
+        let trawquotenl = Item{
             typ: ItemType::ItemRawString,
             pos: 0,
-            val: String::from("`now is{{\n}}the time`")
-        });
+            val: Cow::from("`now is{{\n}}the time`"),
+            line: 0,
+            col: 0
+        };
         let rawquotenl = LexTest{
             name: "raw quote with newline",
             input: "{{`now is{{\n}}the time`}}",
@@ -240,6 +228,38 @@ fn get_tests() -> Vec<LexTest>{
             ]
         };
         s.push(numbertest);
+        let numbers2 = LexTest{
+            name: "number literals",
+            input: "{{0X14 1E3 1_2 0x1.e_fp4 0X1.E_FP4 0b1010 0B1 0o777 0O17 1_000_000 0x1p-2 0x_1P+4 0777 0.5e-3i}}",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemNumber, "0X14"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "1E3"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "1_2"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0x1.e_fp4"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0X1.E_FP4"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0b1010"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0B1"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0o777"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0O17"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "1_000_000"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0x1p-2"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0x_1P+4"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0777"),
+                tspace.clone(), item_factory(ItemType::ItemNumber, "0.5e-3i"),
+                tright.clone(), teof.clone()
+            ]
+        };
+        s.push(numbers2);
+        let complex2 = LexTest{
+            name: "complex literals",
+            input: "{{0x1p2+0b11i -1_0-2.5e1i}}",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemComplex, "0x1p2+0b11i"),
+                tspace.clone(), item_factory(ItemType::ItemComplex, "-1_0-2.5e1i"),
+                tright.clone(), teof.clone()
+            ]
+        };
+        s.push(complex2);
         let charc1 = item_factory(ItemType::ItemCharConstant, r#"'a'"#);
         let charc2 = item_factory(ItemType::ItemCharConstant, r#"'\n'"#);
         let charc3 = item_factory(ItemType::ItemCharConstant, r#"'\''"#);
@@ -316,6 +336,30 @@ fn get_tests() -> Vec<LexTest>{
             ]
         };
         s.push(keywords);
+        let keywords2 = LexTest{
+            name: "more keywords",
+            input: "{{block break continue define template nil}}",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemBlock, "block"),
+                tspace.clone(), item_factory(ItemType::ItemBreak, "break"),
+                tspace.clone(), item_factory(ItemType::ItemContinue, "continue"),
+                tspace.clone(), item_factory(ItemType::ItemDefine, "define"),
+                tspace.clone(), item_factory(ItemType::ItemTemplate, "template"),
+                tspace.clone(), tnil.clone(), tright.clone(), teof.clone()
+            ]
+        };
+        s.push(keywords2);
+        let keywords3 = LexTest{
+            name: "keyword prefixes are identifiers",
+            input: "{{blocks breaking .block}}",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemIdentifier, "blocks"),
+                tspace.clone(), item_factory(ItemType::ItemIdentifier, "breaking"),
+                tspace.clone(), item_factory(ItemType::ItemField, ".block"),
+                tright.clone(), teof.clone()
+            ]
+        };
+        s.push(keywords3);
         let tvar1 = item_factory(ItemType::ItemVariable, "$c");
         let tvar2 = item_factory(ItemType::ItemColonEquals, ":=");
         let tvar3 = item_factory(ItemType::ItemIdentifier, "printf");
@@ -402,6 +446,48 @@ fn get_tests() -> Vec<LexTest>{
             ]
         };
         s.push(dec2);
+        let tassign = item_factory(ItemType::ItemAssign, "=");
+        let assign = LexTest{
+            name: "assignment",
+            input: "{{$v = .Y}}{{$v =3}}",
+            items: vec![
+                tleft.clone(), tdec1.clone(), tspace.clone(),
+                tassign.clone(), tspace.clone(), item_factory(ItemType::ItemField, ".Y"),
+                tright.clone(), tleft.clone(), tdec1.clone(),
+                tspace.clone(), tassign.clone(), tdec3.clone(),
+                tright.clone(), teof.clone()
+            ]
+        };
+        s.push(assign);
+        // 和 Go 一样，= 不是变量名的终结符，所以 $v=3 中间必须有空格
+        let assign2 = LexTest{
+            name: "assignment without space",
+            input: "{{$v=3}}",
+            items: vec![
+                tleft.clone(),
+                item_factory(ItemType::ItemError, "bad character U+003D '='")
+            ]
+        };
+        s.push(assign2);
+        let terrcolon = item_factory(ItemType::ItemError, "expected :=");
+        let colon1 = LexTest{
+            name: "colon without equals",
+            input: "{{$v : 3}}",
+            items: vec![tleft.clone(), tdec1.clone(), tspace.clone(), terrcolon.clone()]
+        };
+        s.push(colon1);
+        let colon2 = LexTest{
+            name: "colon before identifier",
+            input: "{{x:y}}",
+            items: vec![tleft.clone(), item_factory(ItemType::ItemIdentifier, "x"), terrcolon.clone()]
+        };
+        s.push(colon2);
+        let colon3 = LexTest{
+            name: "colon at EOF",
+            input: "{{$v :",
+            items: vec![tleft.clone(), tdec1.clone(), tspace.clone(), terrcolon.clone()]
+        };
+        s.push(colon3);
         let exp1 = item_factory(ItemType::ItemField, ".X");
         let exp2 = item_factory(ItemType::ItemField, ".Y");
         let exp = LexTest{
@@ -415,20 +501,101 @@ fn get_tests() -> Vec<LexTest>{
         };
         s.push(exp);
         let tbctext = item_factory(ItemType::ItemText, "#");
-        let terr0 = item_factory(ItemType::ItemError, "unrecognized character in action: \x01");
+        let terr0 = item_factory(ItemType::ItemError, "unrecognized character in action: U+0001");
         let err0 = LexTest{
             name: "badchar",
             input: "#{{\x01}}",
             items: vec![tbctext.clone(), tleft.clone(), terr0.clone()]
         };
         s.push(err0);
+        let unicode = LexTest{
+            name: "unicode identifiers",
+            input: "{{héllo 本 ٣x ǅ ~}}",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemIdentifier, "héllo"),
+                tspace.clone(), item_factory(ItemType::ItemIdentifier, "本"),
+                tspace.clone(), item_factory(ItemType::ItemIdentifier, "٣x"),
+                tspace.clone(), item_factory(ItemType::ItemIdentifier, "ǅ"),
+                tspace.clone(), item_factory(ItemType::ItemChar, "~"),
+                tright.clone(), teof.clone()
+            ]
+        };
+        s.push(unicode);
+        let unicode_end = LexTest{
+            name: "multibyte character at end of input",
+            input: "{{'é' .本}}本é",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemCharConstant, "'é'"),
+                tspace.clone(), item_factory(ItemType::ItemField, ".本"),
+                tright.clone(), item_factory(ItemType::ItemText, "本é"), teof.clone()
+            ]
+        };
+        s.push(unicode_end);
+        let unicode_errors = vec![
+            ("superscript digit", "{{x²}}", "bad character U+00B2 '²'"),
+            ("combining mark", "{{.e\u{301}}}", "bad character U+0301 '\u{301}'"),
+            ("non-ASCII punctuation", "{{€}}", "unrecognized character in action: U+20AC '€'"),
+            ("non-breaking space", "{{\u{a0}}}", "unrecognized character in action: U+00A0"),
+            ("delete", "{{\u{7f}}}", "unrecognized character in action: U+007F"),
+        ];
+        for (name, input, msg) in unicode_errors{
+            s.push(LexTest{
+                name,
+                input,
+                items: vec![tleft.clone(), item_factory(ItemType::ItemError, msg)]
+            });
+        }
         let terr1 = item_factory(ItemType::ItemError, "unclosed action");
         let err = LexTest{
             name: "unclosed action",
-            input: "{{\n}}",
+            input: "{{",
             items: vec![tleft.clone(), terr1.clone()]
         };
         s.push(err);
+        let newline = LexTest{
+            name: "newline in action",
+            input: "{{\n}}",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemSpace, "\n"),
+                tright.clone(), teof.clone()
+            ]
+        };
+        s.push(newline);
+        let multiline = LexTest{
+            name: "multi-line pipeline",
+            input: "{{$v := .x\r\n\t| printf\n  \"%d\"\n|\nlen\n}}",
+            items: vec![
+                tleft.clone(), tdec1.clone(), tspace.clone(), tdec2.clone(),
+                tspace.clone(), tfield1.clone(),
+                item_factory(ItemType::ItemSpace, "\r\n\t"), tpipe5.clone(),
+                tspace.clone(), tvar3.clone(), item_factory(ItemType::ItemSpace, "\n  "),
+                item_factory(ItemType::ItemString, "\"%d\""),
+                item_factory(ItemType::ItemSpace, "\n"), tpipe5.clone(),
+                item_factory(ItemType::ItemSpace, "\n"),
+                item_factory(ItemType::ItemIdentifier, "len"),
+                item_factory(ItemType::ItemSpace, "\n"), tright.clone(), teof.clone()
+            ]
+        };
+        s.push(multiline);
+        let newline_trim = LexTest{
+            name: "trim markers followed by newlines",
+            input: "a\n{{-\n3\n-}}\nb",
+            items: vec![
+                item_factory(ItemType::ItemText, "a"), tleft.clone(),
+                item_factory(ItemType::ItemNumber, "3"), tright.clone(),
+                item_factory(ItemType::ItemText, "b"), teof.clone()
+            ]
+        };
+        s.push(newline_trim);
+        let newline_eof = LexTest{
+            name: "newline then EOF in action",
+            input: "{{.x\n",
+            items: vec![
+                tleft.clone(), tfield1.clone(),
+                item_factory(ItemType::ItemSpace, "\n"), terr1.clone()
+            ]
+        };
+        s.push(newline_eof);
         let err2 = LexTest{
             name: "EOF in action",
             input: "{{range",
@@ -465,6 +632,23 @@ fn get_tests() -> Vec<LexTest>{
             items: vec![tleft.clone(), terr6.clone()]
         };
         s.push(err6);
+        let bad_numbers = vec![
+            ("bad binary number", "{{0b12}}", "bad number syntax: \"0b12\""),
+            ("bad octal number", "{{0o78}}", "bad number syntax: \"0o78\""),
+            ("bad hex number", "{{0x1g}}", "bad number syntax: \"0x1g\""),
+            ("double hex exponent", "{{0x1p2p}}", "bad number syntax: \"0x1p2p\""),
+            ("hex exponent in decimal number", "{{1.5p3}}", "bad number syntax: \"1.5p\""),
+            ("binary exponent", "{{0b1e3}}", "bad number syntax: \"0b1e\""),
+            ("bad complex", "{{1+2}}", "bad number syntax: \"1+2\""),
+            ("letter after number", "{{3é}}", "bad number syntax: \"3é\""),
+        ];
+        for (name, input, msg) in bad_numbers{
+            s.push(LexTest{
+                name,
+                input,
+                items: vec![tleft.clone(), item_factory(ItemType::ItemError, msg)]
+            });
+        }
         let terr7 = item_factory(ItemType::ItemError, "unclosed left paren");
         let terr8 = item_factory(ItemType::ItemNumber, "3");
         let err7 = LexTest{
@@ -522,39 +706,311 @@ fn get_tests() -> Vec<LexTest>{
             ]
         };
         s.push(err13);
+        let ttrim1 = item_factory(ItemType::ItemText, "hello-");
+        let ttrim2 = item_factory(ItemType::ItemText, "-world");
+        let trim = LexTest{
+            name: "trimming spaces before and after",
+            input: "hello- {{- 3 -}} -world",
+            items: vec![
+                ttrim1.clone(), tleft.clone(), tnumber3.clone(),
+                tright.clone(), ttrim2.clone(), teof.clone()
+            ]
+        };
+        s.push(trim);
+        let ttrim3 = item_factory(ItemType::ItemNumber, "-3");
+        let trim2 = LexTest{
+            name: "minus without space is not a trim marker",
+            input: "hello {{-3}} world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello "), tleft.clone(),
+                ttrim3.clone(), tright.clone(),
+                item_factory(ItemType::ItemText, " world"), teof.clone()
+            ]
+        };
+        s.push(trim2);
+        let trim3 = LexTest{
+            name: "trimming newlines and several spaces",
+            input: "hello \n\t {{- .x   -}} \n\n world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello"), tleft.clone(),
+                tfield1.clone(), item_factory(ItemType::ItemSpace, "  "),
+                tright.clone(), item_factory(ItemType::ItemText, "world"),
+                teof.clone()
+            ]
+        };
+        s.push(trim3);
+        let trim4 = LexTest{
+            name: "trimming everything",
+            input: "  {{- 3 -}}  ",
+            items: vec![tleft.clone(), tnumber3.clone(), tright.clone(), teof.clone()]
+        };
+        s.push(trim4);
+        let trim5 = LexTest{
+            name: "trimming before comment",
+            input: "hello- {{- /* hello */}} -world",
+            items: vec![
+                ttrim1.clone(), item_factory(ItemType::ItemText, " -world"),
+                teof.clone()
+            ]
+        };
+        s.push(trim5);
+        let trim6 = LexTest{
+            name: "trimming spaces before and after comment",
+            input: "hello- {{- /* hello */ -}} -world",
+            items: vec![ttrim1.clone(), ttrim2.clone(), teof.clone()]
+        };
+        s.push(trim6);
+        let trim7 = LexTest{
+            name: "trimming after comment",
+            input: "hello- {{/* hello */ -}}\n -world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello- "), ttrim2.clone(),
+                teof.clone()
+            ]
+        };
+        s.push(trim7);
+        let terr15 = item_factory(ItemType::ItemError, "comment ends before closing delimiter");
+        let trim8 = LexTest{
+            name: "comment with trim marker but no space",
+            input: "hello- {{- /* hello */-}} -world",
+            items: vec![ttrim1.clone(), terr15.clone()]
+        };
+        s.push(trim8);
         s
     };
     lextests
 }
 
-fn item_factory(typ: ItemType, val: &'static str) -> Rc<Item>{
-    Rc::new(Item{
-        typ: typ,
+fn get_delim_tests() -> Vec<LexTest>{
+    let tleft = item_factory(ItemType::ItemLeftDelim, "$$");
+    let tright = item_factory(ItemType::ItemRightDelim, "@@");
+    let teof = item_factory(ItemType::ItemEOF, "");
+    vec![
+        LexTest{
+            name: "punctuation",
+            input: "$$,@%{{}}@@",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemChar, ","),
+                item_factory(ItemType::ItemChar, "@"), item_factory(ItemType::ItemChar, "%"),
+                item_factory(ItemType::ItemChar, "{"), item_factory(ItemType::ItemChar, "{"),
+                item_factory(ItemType::ItemChar, "}"), item_factory(ItemType::ItemChar, "}"),
+                tright.clone(), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "empty action",
+            input: "$$@@",
+            items: vec![tleft.clone(), tright.clone(), teof.clone()]
+        },
+        LexTest{
+            name: "for",
+            input: "$$for@@",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemIdentifier, "for"),
+                tright.clone(), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "quote",
+            input: r#"$$"abc \n\t\" "@@"#,
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemString, r#""abc \n\t\" ""#),
+                tright.clone(), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "raw quote",
+            input: "$$`abc\n\t\" `@@",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemRawString, "`abc\n\t\" `"),
+                tright.clone(), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "comment",
+            input: "hello-$$/* this is a comment */@@-world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello-"),
+                item_factory(ItemType::ItemText, "-world"), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "comment with trim markers",
+            input: "hello- $$- /* this is a comment */ -@@ -world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello-"),
+                item_factory(ItemType::ItemText, "-world"), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "comment closed by default delimiter",
+            input: "hello-$$/* this is a comment */}}-world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello-"),
+                item_factory(ItemType::ItemError, "comment ends before closing delimiter")
+            ]
+        },
+        LexTest{
+            name: "unclosed comment",
+            input: "hello-$$/* this is a comment @@-world",
+            items: vec![
+                item_factory(ItemType::ItemText, "hello-"),
+                item_factory(ItemType::ItemError, "unclosed comment")
+            ]
+        },
+        LexTest{
+            name: "trim markers",
+            input: "a  $$- .x -@@  b",
+            items: vec![
+                item_factory(ItemType::ItemText, "a"), tleft.clone(),
+                item_factory(ItemType::ItemField, ".x"), tright.clone(),
+                item_factory(ItemType::ItemText, "b"), teof.clone()
+            ]
+        },
+    ]
+}
+
+fn get_pos_tests() -> Vec<LexTest>{
+    vec![
+        LexTest{
+            name: "empty",
+            input: "",
+            items: vec![item_pos(ItemType::ItemEOF, 0, 1, 1, "")]
+        },
+        LexTest{
+            name: "punctuation",
+            input: "{{,@%#}}",
+            items: vec![
+                item_pos(ItemType::ItemLeftDelim, 0, 1, 1, "{{"),
+                item_pos(ItemType::ItemChar, 2, 1, 3, ","),
+                item_pos(ItemType::ItemChar, 3, 1, 4, "@"),
+                item_pos(ItemType::ItemChar, 4, 1, 5, "%"),
+                item_pos(ItemType::ItemChar, 5, 1, 6, "#"),
+                item_pos(ItemType::ItemRightDelim, 6, 1, 7, "}}"),
+                item_pos(ItemType::ItemEOF, 8, 1, 9, ""),
+            ]
+        },
+        LexTest{
+            name: "sample",
+            input: "0123{{hello}}xyz",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "0123"),
+                item_pos(ItemType::ItemLeftDelim, 4, 1, 5, "{{"),
+                item_pos(ItemType::ItemIdentifier, 6, 1, 7, "hello"),
+                item_pos(ItemType::ItemRightDelim, 11, 1, 12, "}}"),
+                item_pos(ItemType::ItemText, 13, 1, 14, "xyz"),
+                item_pos(ItemType::ItemEOF, 16, 1, 17, ""),
+            ]
+        },
+        LexTest{
+            name: "trimafter",
+            input: "{{x -}}\n{{y}}",
+            items: vec![
+                item_pos(ItemType::ItemLeftDelim, 0, 1, 1, "{{"),
+                item_pos(ItemType::ItemIdentifier, 2, 1, 3, "x"),
+                item_pos(ItemType::ItemRightDelim, 5, 1, 6, "}}"),
+                item_pos(ItemType::ItemLeftDelim, 8, 2, 1, "{{"),
+                item_pos(ItemType::ItemIdentifier, 10, 2, 3, "y"),
+                item_pos(ItemType::ItemRightDelim, 11, 2, 4, "}}"),
+                item_pos(ItemType::ItemEOF, 13, 2, 6, ""),
+            ]
+        },
+        LexTest{
+            name: "trimbefore",
+            input: "{{x}}\n{{- y}}",
+            items: vec![
+                item_pos(ItemType::ItemLeftDelim, 0, 1, 1, "{{"),
+                item_pos(ItemType::ItemIdentifier, 2, 1, 3, "x"),
+                item_pos(ItemType::ItemRightDelim, 3, 1, 4, "}}"),
+                item_pos(ItemType::ItemLeftDelim, 6, 2, 1, "{{"),
+                item_pos(ItemType::ItemIdentifier, 10, 2, 5, "y"),
+                item_pos(ItemType::ItemRightDelim, 11, 2, 6, "}}"),
+                item_pos(ItemType::ItemEOF, 13, 2, 8, ""),
+            ]
+        },
+        LexTest{
+            name: "trimtext",
+            input: "ab \n{{- x -}}\n cd",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "ab"),
+                item_pos(ItemType::ItemLeftDelim, 4, 2, 1, "{{"),
+                item_pos(ItemType::ItemIdentifier, 8, 2, 5, "x"),
+                item_pos(ItemType::ItemRightDelim, 11, 2, 8, "}}"),
+                item_pos(ItemType::ItemText, 15, 3, 2, "cd"),
+                item_pos(ItemType::ItemEOF, 17, 3, 4, ""),
+            ]
+        },
+        LexTest{
+            name: "multibyte",
+            input: "本a{{x}}\n éb",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "本a"),
+                item_pos(ItemType::ItemLeftDelim, 4, 1, 3, "{{"),
+                item_pos(ItemType::ItemIdentifier, 6, 1, 5, "x"),
+                item_pos(ItemType::ItemRightDelim, 7, 1, 6, "}}"),
+                item_pos(ItemType::ItemText, 9, 1, 8, "\n éb"),
+                item_pos(ItemType::ItemEOF, 14, 2, 4, ""),
+            ]
+        },
+        LexTest{
+            name: "multi-line action",
+            input: "{{x\n  .y}}",
+            items: vec![
+                item_pos(ItemType::ItemLeftDelim, 0, 1, 1, "{{"),
+                item_pos(ItemType::ItemIdentifier, 2, 1, 3, "x"),
+                item_pos(ItemType::ItemSpace, 3, 1, 4, "\n  "),
+                item_pos(ItemType::ItemField, 6, 2, 3, ".y"),
+                item_pos(ItemType::ItemRightDelim, 8, 2, 5, "}}"),
+                item_pos(ItemType::ItemEOF, 10, 2, 7, ""),
+            ]
+        },
+        LexTest{
+            name: "comment",
+            input: "a\n{{/* b\nc */}}\nd{{3k}}",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "a\n"),
+                item_pos(ItemType::ItemText, 15, 3, 7, "\nd"),
+                item_pos(ItemType::ItemLeftDelim, 17, 4, 2, "{{"),
+                item_pos(ItemType::ItemError, 19, 4, 4, "bad number syntax: \"3k\""),
+            ]
+        },
+    ]
+}
+
+fn item_factory(typ: ItemType, val: &'static str) -> Item<'static>{
+    Item{
+        typ,
         pos: 0,
-        val: String::from(val)
-    })
+        val: Cow::from(val),
+        line: 0,
+        col: 0
+    }
 }
 
-fn collect(t: &LexTest, left: &'static str, right: &'static str)->Vec<Rc<Item>>{
-    let mut l = lex(t.name, t.input, left, right);
-    l.run();
-    let mut data: Vec<Rc<Item>> = Vec::new();
-    loop{
-        let item = l.next_item();
-        match item{
-            None => break,
-            Some(r) => {
-                data.push(r.clone());
-                if r.typ == ItemType::ItemEOF || r.typ == ItemType::ItemError{
-                    break
-                }
-            }
+fn item_pos(typ: ItemType, pos: Pos, line: usize, col: usize, val: &'static str) -> Item<'static>{
+    Item{
+        typ,
+        pos,
+        val: Cow::from(val),
+        line,
+        col
+    }
+}
+
+fn collect<'a>(t: &LexTest, left: &'a str, right: &'a str)->Vec<Item<'a>>{
+    let l = lex(t.name, t.input, left, right);
+    let mut data: Vec<Item> = Vec::new();
+    for item in l{
+        let done = item.typ == ItemType::ItemEOF || item.typ == ItemType::ItemError;
+        data.push(item);
+        if done{
+            break
         }
     }
     data
 }
 
-fn equal(i1: &Vec<Rc<Item>>, i2: &Vec<Rc<Item>>, check_pos: bool)->bool{
+fn equal(i1: &[Item], i2: &[Item], check_pos: bool)->bool{
     if i1.len() != i2.len(){
         println!("{} len not equal {}", i1.len(), i2.len());
         // let mut i = 0;
@@ -577,37 +1033,516 @@ fn equal(i1: &Vec<Rc<Item>>, i2: &Vec<Rc<Item>>, check_pos: bool)->bool{
             println!("{}, {:?} , {:?} pos not equal", k, i1[k], i2[k]);
             return false;
         }
+        if check_pos && (i1[k].line != i2[k].line || i1[k].col != i2[k].col){
+            println!("{}, {:?} , {:?} line not equal", k, i1[k], i2[k]);
+            return false;
+        }
     }
-    return true;
+    true
 }
 
 #[test]
 fn test_lex(){
     let lextests = get_tests();
-    let mut i = 0;
-    for test in lextests{
-        let items = collect(&test, "", "");
+    for (i, test) in lextests.iter().enumerate(){
+        let items = collect(test, "", "");
         // println!("{}", test.name);
         // println!("{}", i);
         if !equal(&items, &test.items, false){
             println!("{:?}\n\n", items);
             println!("{:?}", test.items);
             println!("{}", i);
-            assert!(false); 
+            panic!("tokens not equal");
         }
-        i = i+1; 
     }
 }
 
-// #[test]
-fn test_lex_spec(){
-    let lextests = get_tests();
-    let test = lextests.get(24).unwrap();
-    println!("{}", &test.name);
-    let items = collect(&test, "", "");
+#[test]
+fn test_char_classes(){
+    // (字符, is_print, is_alphanumeric, is_space)
+    let tests = vec![
+        ('a', true, true, false),
+        ('Z', true, true, false),
+        ('_', true, true, false),
+        ('7', true, true, false),
+        ('~', true, false, false),
+        (' ', true, false, true),
+        ('\t', false, false, true),
+        ('\n', false, false, true),
+        ('\r', false, false, true),
+        ('\u{7f}', false, false, false),
+        ('é', true, true, false),
+        ('本', true, true, false),
+        ('ǅ', true, true, false),        // Lt
+        ('ʰ', true, true, false),        // Lm
+        ('٣', true, true, false),        // Nd
+        ('²', true, false, false),       // No
+        ('Ⅻ', true, false, false),       // Nl
+        ('\u{301}', true, false, false), // Mn
+        ('€', true, false, false),       // Sc
+        ('—', true, false, false),       // Pd
+        ('😀', true, false, false),      // So
+        ('\u{a0}', false, false, false), // Zs
+        ('\u{3000}', false, false, false), // Zs
+        ('\u{2028}', false, false, false), // Zl
+        ('\u{200b}', false, false, false), // Cf
+        ('\u{e000}', false, false, false), // Co
+        ('\u{ffff}', false, false, false), // Cn
+    ];
+    for (r, print, alnum, space) in tests{
+        assert!(is_print(r) == print, "is_print({:?})", r);
+        assert!(is_alphanumeric(Some(r)) == alnum, "is_alphanumeric({:?})", r);
+        assert!(is_space(r) == space, "is_space({:?})", r);
+        assert!(is_end_of_line(r) == (r == '\n' || r == '\r'), "is_end_of_line({:?})", r);
+    }
+    assert!(!is_alphanumeric(None));
+    assert_eq!(format_rune('\u{1}'), "U+0001");
+    assert_eq!(format_rune('本'), "U+672C '本'");
+    assert_eq!(format_rune('😀'), "U+1F600 '😀'");
+}
+
+#[test]
+fn test_delims(){
+    for test in get_delim_tests(){
+        let items = collect(&test, "$$", "@@");
+        if !equal(&items, &test.items, false){
+            println!("{}: {:?}\n\n", test.name, items);
+            println!("{:?}", test.items);
+            panic!("tokens not equal");
+        }
+    }
+}
+
+#[test]
+fn test_delims_and_markers(){
+    // 分隔符本身看起来就像 trim 标记
+    let test = LexTest{
+        name: "delims that look like markers",
+        input: "{{- .x -}} {{- - .x - -}}",
+        items: vec![
+            item_factory(ItemType::ItemLeftDelim, "{{- "),
+            item_factory(ItemType::ItemField, ".x"),
+            item_factory(ItemType::ItemRightDelim, " -}}"),
+            item_factory(ItemType::ItemLeftDelim, "{{- "),
+            item_factory(ItemType::ItemField, ".x"),
+            item_factory(ItemType::ItemRightDelim, " -}}"),
+            item_factory(ItemType::ItemEOF, ""),
+        ]
+    };
+    let items = collect(&test, "{{- ", " -}}");
     if !equal(&items, &test.items, false){
-        println!("{:?}\n", items);
+        println!("{:?}\n\n", items);
         println!("{:?}", test.items);
-        assert!(false);
+        panic!("tokens not equal");
+    }
+}
+
+#[test]
+fn test_pos(){
+    for test in get_pos_tests(){
+        let items = collect(&test, "", "");
+        if !equal(&items, &test.items, true){
+            println!("{}: {:?}\n\n", test.name, items);
+            println!("{:?}", test.items);
+            panic!("tokens not equal");
+        }
+    }
+}
+
+#[test]
+fn test_lex_owned_input(){
+    // 模板内容和分隔符都在运行时生成，不是 'static 的字面量
+    let input: String = ["$$", "for", "@@", " ", "$$", ".x", "@@"].concat();
+    let left = String::from("$$");
+    let right = String::from("@@");
+    let name = format!("{}-{}", "owned", 1);
+    let mut data: Vec<Item> = Vec::new();
+    {
+        // item 借用了 input，要在 input 被释放之后继续使用就得先转换成 owned
+        let l = lex(&name, &input, &left, &right);
+        for item in l{
+            data.push(item.into_owned());
+        }
+    }
+    drop(input);
+    let expected = vec![
+        item_factory(ItemType::ItemLeftDelim, "$$"),
+        item_factory(ItemType::ItemIdentifier, "for"),
+        item_factory(ItemType::ItemRightDelim, "@@"),
+        item_factory(ItemType::ItemText, " "),
+        item_factory(ItemType::ItemLeftDelim, "$$"),
+        item_factory(ItemType::ItemField, ".x"),
+        item_factory(ItemType::ItemRightDelim, "@@"),
+        item_factory(ItemType::ItemEOF, ""),
+    ];
+    assert!(equal(&data, &expected, false));
+}
+
+#[test]
+fn test_lex_zero_copy(){
+    // 除了错误信息以外，item 的 val 都直接指向模板的源码
+    let input = String::from("hello {{.x | printf \"%d\" 3}} world {{3k}}");
+    let items: Vec<Item> = lex("zero copy", &input, "", "").collect();
+    for item in &items{
+        match item.val{
+            Cow::Borrowed(val) => {
+                assert!(item.typ != ItemType::ItemError);
+                assert_eq!(val.as_ptr(), input[item.pos..].as_ptr());
+            },
+            Cow::Owned(_) => assert!(item.typ == ItemType::ItemError),
+        }
+    }
+    let owned: Vec<Item<'static>> = items.into_iter().map(|item| item.into_owned()).collect();
+    drop(input);
+    assert_eq!(owned[0].val, "hello ");
+    assert_eq!(owned.last().unwrap().val, "bad number syntax: \"3k\"");
+}
+
+#[test]
+fn test_lex_lazy(){
+    // 只取第一个 token 时，后面的内容还不应该被扫描
+    let input = "hello {{.x}} world {{3k}}";
+    let mut l = lex("lazy", input, "", "");
+    let first = l.next_item().unwrap();
+    assert!(first.typ == ItemType::ItemText);
+    assert_eq!(first.val, "hello ");
+    assert!(l.pos < input.len());
+    let rest: Vec<Item> = l.collect();
+    let last = rest.last().unwrap();
+    assert!(last.typ == ItemType::ItemError);
+    assert_eq!(last.val, "bad number syntax: \"3k\"");
+}
+
+#[test]
+fn test_lex_stops_after_error(){
+    // 出错之后迭代器结束，不再产生 item
+    let mut l = lex("error", "{{3)}} {{.x}}", "", "");
+    let items: Vec<Item> = l.by_ref().collect();
+    assert!(items.last().unwrap().typ == ItemType::ItemError);
+    assert!(l.next().is_none());
+    assert!(l.next_item().is_none());
+}
+
+#[test]
+fn test_lex_recover(){
+    let input = "{{3k}} a {{\"x\n}} b {{)}} c{{/* x";
+    let l = LexerOptions::new().recover(true).lex("recover", input);
+    let items: Vec<Item> = l.collect();
+    let expected = vec![
+        item_pos(ItemType::ItemLeftDelim, 0, 1, 1, "{{"),
+        item_pos(ItemType::ItemError, 2, 1, 3, "bad number syntax: \"3k\""),
+        item_pos(ItemType::ItemText, 6, 1, 7, " a "),
+        item_pos(ItemType::ItemLeftDelim, 9, 1, 10, "{{"),
+        item_pos(ItemType::ItemError, 11, 1, 12, "unterminated quoted string"),
+        item_pos(ItemType::ItemText, 14, 2, 1, "}} b "),
+        item_pos(ItemType::ItemLeftDelim, 19, 2, 6, "{{"),
+        item_pos(ItemType::ItemRightParen, 21, 2, 8, ")"),
+        item_pos(ItemType::ItemError, 22, 2, 9, "unexpected right paren ')'"),
+        item_pos(ItemType::ItemText, 24, 2, 11, " c"),
+        item_pos(ItemType::ItemError, 28, 2, 15, "unclosed comment"),
+        item_pos(ItemType::ItemEOF, 32, 2, 19, ""),
+    ];
+    if !equal(&items, &expected, true){
+        println!("{:?}\n\n", items);
+        println!("{:?}", expected);
+        panic!("tokens not equal");
+    }
+}
+
+#[test]
+fn test_lex_recover_table(){
+    // 恢复模式下，原来的测试用例遇到错误后都应该继续扫描到 EOF
+    for test in get_tests(){
+        let options = LexerOptions::new().recover(true);
+        let items: Vec<Item> = options.lex(test.name, test.input).collect();
+        assert!(items.last().unwrap().typ == ItemType::ItemEOF, "{}", test.name);
+        let errors = items.iter().filter(|i| i.typ == ItemType::ItemError).count();
+        let expected = test.items.iter().filter(|i| i.typ == ItemType::ItemError).count();
+        assert!(errors >= expected, "{}", test.name);
+    }
+}
+
+#[test]
+fn test_lex_options(){
+    let teof = item_factory(ItemType::ItemEOF, "");
+    let tleft = item_factory(ItemType::ItemLeftDelim, "<%");
+    let tright = item_factory(ItemType::ItemRightDelim, "%>");
+    let options = LexerOptions::new().delims("<%", "%>").comments("#", "#");
+    let tests = vec![
+        LexTest{
+            name: "custom comment markers",
+            input: "a<%# comment with */ inside #%>b",
+            items: vec![
+                item_factory(ItemType::ItemText, "a"),
+                item_factory(ItemType::ItemText, "b"), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "custom comment markers with trim",
+            input: "a <%- # comment # -%> b",
+            items: vec![
+                item_factory(ItemType::ItemText, "a"),
+                item_factory(ItemType::ItemText, "b"), teof.clone()
+            ]
+        },
+        LexTest{
+            name: "default comment markers are not comments",
+            input: "<%/* x */%>",
+            items: vec![
+                tleft.clone(), item_factory(ItemType::ItemChar, "/"),
+                item_factory(ItemType::ItemChar, "*"), item_factory(ItemType::ItemSpace, " "),
+                item_factory(ItemType::ItemIdentifier, "x"), item_factory(ItemType::ItemSpace, " "),
+                item_factory(ItemType::ItemChar, "*"), item_factory(ItemType::ItemChar, "/"),
+                tright.clone(), teof.clone()
+            ]
+        },
+    ];
+    for test in tests{
+        let items: Vec<Item> = options.lex(test.name, test.input).collect();
+        if !equal(&items, &test.items, false){
+            println!("{}: {:?}\n\n", test.name, items);
+            println!("{:?}", test.items);
+            panic!("tokens not equal");
+        }
+    }
+
+    // 空字符串表示默认的分隔符和注释标记
+    let reset = options.clone().delims("", "").comments("", "");
+    let items: Vec<Item> = reset.lex("reset", "{{/* c */}}{{.x}}").collect();
+    let expected = vec![
+        item_factory(ItemType::ItemLeftDelim, "{{"),
+        item_factory(ItemType::ItemField, ".x"),
+        item_factory(ItemType::ItemRightDelim, "}}"),
+        item_factory(ItemType::ItemEOF, ""),
+    ];
+    assert!(equal(&items, &expected, false));
+
+    // 不允许换行的严格模式
+    let strict = LexerOptions::new().allow_newlines(false);
+    let items: Vec<Item> = strict.lex("strict", "{{.x\n}}").collect();
+    let expected = vec![
+        item_factory(ItemType::ItemLeftDelim, "{{"),
+        item_factory(ItemType::ItemField, ".x"),
+        item_factory(ItemType::ItemError, "unclosed action"),
+    ];
+    assert!(equal(&items, &expected, false));
+
+    // 关闭 break/continue 后它们就是普通的函数名
+    let old = LexerOptions::new()
+        .keyword(ItemType::ItemBreak, false)
+        .keyword(ItemType::ItemContinue, false)
+        .keyword(ItemType::ItemContinue, true)
+        .keyword(ItemType::ItemBreak, false);
+    let items: Vec<Item> = old.lex("old", "{{break continue}}").collect();
+    let expected = vec![
+        item_factory(ItemType::ItemLeftDelim, "{{"),
+        item_factory(ItemType::ItemIdentifier, "break"),
+        item_factory(ItemType::ItemSpace, " "),
+        item_factory(ItemType::ItemContinue, "continue"),
+        item_factory(ItemType::ItemRightDelim, "}}"),
+        item_factory(ItemType::ItemEOF, ""),
+    ];
+    assert!(equal(&items, &expected, false));
+}
+
+fn keyword_types(options: &LexerOptions) -> Vec<ItemType>{
+    let input = "{{block break continue define else end if nil range template with .}}";
+    options.lex("keywords", input)
+        .filter(|i| i.typ != ItemType::ItemSpace)
+        .map(|i| i.typ)
+        .collect()
+}
+
+#[test]
+fn test_lex_dialects(){
+    use super::lex::ItemType::*;
+    // 默认和现在的 Go 一样，所有关键字都打开
+    let extended = keyword_types(&LexerOptions::new());
+    assert_eq!(extended, vec![
+        ItemLeftDelim, ItemBlock, ItemBreak, ItemContinue, ItemDefine, ItemElse, ItemEnd,
+        ItemIf, ItemNil, ItemRange, ItemTemplate, ItemWith, ItemDot, ItemRightDelim, ItemEOF,
+    ]);
+    assert_eq!(keyword_types(&LexerOptions::default()), extended);
+    // 严格模式只有 Go 1.0 的关键字
+    assert_eq!(keyword_types(&LexerOptions::strict()), vec![
+        ItemLeftDelim, ItemIdentifier, ItemIdentifier, ItemIdentifier, ItemDefine, ItemElse, ItemEnd,
+        ItemIf, ItemNil, ItemRange, ItemTemplate, ItemWith, ItemDot, ItemRightDelim, ItemEOF,
+    ]);
+    // 在严格模式的基础上单独打开一个扩展关键字
+    let types = keyword_types(&LexerOptions::strict().keyword(ItemBlock, true));
+    assert_eq!(&types[1..4], &[ItemBlock, ItemIdentifier, ItemIdentifier]);
+}
+
+#[test]
+#[should_panic(expected = "end is not an extension keyword")]
+fn test_lex_core_keyword_cannot_be_disabled(){
+    LexerOptions::new().keyword(ItemType::ItemEnd, false);
+}
+
+#[test]
+fn test_lex_core_keyword_ignored_in_lexer(){
+    // 直接改 Lexer 的字段也关不掉核心关键字
+    let mut l = LexerOptions::new().lex("core", "{{if}}");
+    l.disabled_keywords.push(ItemType::ItemIf);
+    let types: Vec<ItemType> = l.map(|i| i.typ).collect();
+    assert_eq!(types, vec![ItemType::ItemLeftDelim, ItemType::ItemIf, ItemType::ItemRightDelim, ItemType::ItemEOF]);
+}
+
+#[test]
+fn test_lex_comments(){
+    let options = LexerOptions::new().emit_comments(true);
+    let tests = vec![
+        LexTest{
+            name: "text with comment",
+            input: "hello-{{/* this is a comment */}}-world",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "hello-"),
+                item_pos(ItemType::ItemComment, 8, 1, 9, "/* this is a comment */"),
+                item_pos(ItemType::ItemText, 33, 1, 34, "-world"),
+                item_pos(ItemType::ItemEOF, 39, 1, 40, ""),
+            ]
+        },
+        LexTest{
+            name: "multi-line comment with trim markers",
+            input: "hello- {{- /* a\nb */ -}}\n-world",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "hello-"),
+                item_pos(ItemType::ItemComment, 11, 1, 12, "/* a\nb */"),
+                item_pos(ItemType::ItemText, 25, 3, 1, "-world"),
+                item_pos(ItemType::ItemEOF, 31, 3, 7, ""),
+            ]
+        },
+        LexTest{
+            name: "comments between actions",
+            input: "{{.x}}{{/**/}}{{.y}}",
+            items: vec![
+                item_pos(ItemType::ItemLeftDelim, 0, 1, 1, "{{"),
+                item_pos(ItemType::ItemField, 2, 1, 3, ".x"),
+                item_pos(ItemType::ItemRightDelim, 4, 1, 5, "}}"),
+                item_pos(ItemType::ItemComment, 8, 1, 9, "/**/"),
+                item_pos(ItemType::ItemLeftDelim, 14, 1, 15, "{{"),
+                item_pos(ItemType::ItemField, 16, 1, 17, ".y"),
+                item_pos(ItemType::ItemRightDelim, 18, 1, 19, "}}"),
+                item_pos(ItemType::ItemEOF, 20, 1, 21, ""),
+            ]
+        },
+        LexTest{
+            name: "bad comment",
+            input: "hello-{{/* */ }}-world",
+            items: vec![
+                item_pos(ItemType::ItemText, 0, 1, 1, "hello-"),
+                item_pos(ItemType::ItemError, 8, 1, 9, "comment ends before closing delimiter"),
+            ]
+        },
+    ];
+    for test in tests{
+        let items: Vec<Item> = options.lex(test.name, test.input).collect();
+        if !equal(&items, &test.items, true){
+            println!("{}: {:?}\n\n", test.name, items);
+            println!("{:?}", test.items);
+            panic!("tokens not equal");
+        }
+    }
+    let custom = LexerOptions::new().emit_comments(true).delims("[[", "]]").comments("#", "#");
+    let items: Vec<Item> = custom.lex("custom", "[[# hi #]]").collect();
+    let expected = vec![
+        item_factory(ItemType::ItemComment, "# hi #"),
+        item_factory(ItemType::ItemEOF, ""),
+    ];
+    assert!(equal(&items, &expected, false));
+}
+
+#[test]
+fn test_lex_lossless(){
+    let options = LexerOptions::new().lossless(true);
+    let items: Vec<Item> = options.lex("lossless", "a \n{{- /* c */ -}} b {{- .x -}}\n").collect();
+    let expected = vec![
+        item_factory(ItemType::ItemText, "a"),
+        item_factory(ItemType::ItemTrivia, " \n"),
+        item_factory(ItemType::ItemTrivia, "{{- "),
+        item_factory(ItemType::ItemTrivia, "/* c */ -}} "),
+        item_factory(ItemType::ItemText, "b"),
+        item_factory(ItemType::ItemTrivia, " "),
+        item_factory(ItemType::ItemLeftDelim, "{{"),
+        item_factory(ItemType::ItemTrivia, "- "),
+        item_factory(ItemType::ItemField, ".x"),
+        item_factory(ItemType::ItemTrivia, " -"),
+        item_factory(ItemType::ItemRightDelim, "}}"),
+        item_factory(ItemType::ItemTrivia, "\n"),
+        item_factory(ItemType::ItemEOF, ""),
+    ];
+    if !equal(&items, &expected, false){
+        println!("{:?}\n\n", items);
+        println!("{:?}", expected);
+        panic!("tokens not equal");
+    }
+}
+
+#[test]
+fn test_lex_lossless_roundtrip(){
+    // 把所有 item 的 val 拼起来应该和输入完全一样，出错的部分在恢复模式下也会作为 trivia 保留
+    let check = |test: &LexTest, options: &LexerOptions|{
+        let mut out = String::new();
+        for item in options.lex(test.name, test.input){
+            if item.typ != ItemType::ItemError{
+                out.push_str(&item.val);
+            }
+        }
+        assert_eq!(out, test.input, "{}", test.name);
+    };
+    let default = LexerOptions::new().lossless(true).recover(true);
+    let comments = default.clone().emit_comments(true);
+    for test in get_tests(){
+        check(&test, &default);
+        check(&test, &comments);
+    }
+    let delims = LexerOptions::new().lossless(true).recover(true).delims("$$", "@@");
+    for test in get_delim_tests(){
+        check(&test, &delims);
+    }
+    for test in get_pos_tests(){
+        check(&test, &default);
+    }
+    // 不打开 recover 也一样
+    let plain = LexerOptions::new().lossless(true);
+    for test in get_tests(){
+        check(&test, &plain);
+    }
+}
+
+#[test]
+fn test_lex_lossless_without_recover(){
+    // 出错后停止扫描，剩下的输入整个作为 trivia 发出
+    let items: Vec<Item> = LexerOptions::new().lossless(true).lex("x", "{{3k}} rest").collect();
+    let expected = vec![
+        item_factory(ItemType::ItemLeftDelim, "{{"),
+        item_factory(ItemType::ItemError, r#"bad number syntax: "3k""#),
+        item_factory(ItemType::ItemTrivia, "3k}} rest"),
+    ];
+    if !equal(&items, &expected, false){
+        println!("{:?}\n\n", items);
+        println!("{:?}", expected);
+        panic!("tokens not equal");
+    }
+}
+
+#[test]
+fn test_item_display(){
+    let tests = vec![
+        (item_factory(ItemType::ItemEOF, ""), "EOF"),
+        (item_factory(ItemType::ItemError, "unclosed action"), "unclosed action"),
+        (item_factory(ItemType::ItemIf, "if"), "<if>"),
+        (item_factory(ItemType::ItemDot, "."), "<.>"),
+        (item_factory(ItemType::ItemText, "abc"), r#""abc""#),
+        (item_factory(ItemType::ItemString, r#""a\tb""#), r#""\"a\\tb\"""#),
+        (item_factory(ItemType::ItemText, "a\tb\x01\u{00ad}é"), r#""a\tb\x01\u00adé""#),
+        (item_factory(ItemType::ItemText, "now is the time"), r#""now is the"..."#),
+        (item_factory(ItemType::ItemText, "héllo wörl"), r#""héllo wörl"..."#),
+    ];
+    for (item, expected) in tests{
+        assert_eq!(format!("{}", item), expected);
     }
+    assert_eq!(ItemType::ItemLeftDelim.to_string(), "left delim");
+    assert_eq!(ItemType::ItemColonEquals.to_string(), ":=");
+    assert_eq!(format!("{}", ItemType::ItemWith), "with");
 }
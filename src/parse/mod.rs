@@ -1,3 +1,14 @@
 pub mod node;
 pub mod lex;
+// 和 Go 的 text/template/parse 包一样，解析器就放在 parse::parse 里
+#[allow(clippy::module_inception)]
 pub mod parse;
+pub mod strconv;
+#[cfg(test)]
+mod lex_test;
+#[cfg(test)]
+mod node_test;
+#[cfg(test)]
+mod strconv_test;
+#[cfg(test)]
+mod parse_test;
@@ -1,434 +1,895 @@
-use std::rc::Rc;
-use std::cell::RefCell;
-use std::any::Any;
-use std::mem;
-use super::parse::*;
+use super::lex::{quote, ItemType};
+use super::strconv::{parse_number, Number};
 pub type Pos = usize;
 
-trait posTrait{
-    fn position(self) -> Pos;
-}
-impl posTrait for Pos{
-    fn position(self) -> Pos{
-        return self;
-    }
-}
-
+// 语法树的节点。每种节点都是一个具体的类型，通过 match 就能拿到，不需要 downcast。
+// 节点不再保存所属 Tree 的引用，树和节点之间没有循环引用
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeType{
-    // EnumText(TextNode),
+    EnumAction(ActionNode),
+    EnumBool(BoolNode),
+    EnumBreak(BreakNode),
+    EnumChain(ChainNode),
+    EnumCommand(CommandNode),
+    EnumComment(CommentNode),
+    EnumContinue(ContinueNode),
+    EnumDot(DotNode),
+    EnumElse(ElseNode),
+    EnumEnd(EndNode),
+    EnumField(FieldNode),
+    EnumIdentifier(IdentifierNode),
+    EnumIf(IfNode),
     EnumList(ListNode),
-    // NodeAction,
-    // NodeBool,
-    // NodeChain,
-    // NodeCommand,
-    // NodeDot,
-    // NodeElse,
-    // NodeEnd,
-    // NodeField,
-    // NodeIdentifier,
-    // NodeIf,
-    // NodeNil,
-    // NodeNumber,
-    // NodePipe,
-    // NodeRange,
-    // NodeString,
-    // NodeTemplate,
-    // NodeVariable,
-    // NodeWith
-}
-
-fn match_node<U, F:Fn(Node) -> U>(node: &NodeType, f:F) -> Option<String>{
-    match node{
-        &NodeType::EnumList(ref n) => Some(f(n)),
-        // _ => None
-    }
+    EnumNil(NilNode),
+    EnumNumber(NumberNode),
+    EnumPipe(PipeNode),
+    EnumRange(RangeNode),
+    EnumString(StringNode),
+    EnumTemplate(TemplateNode),
+    EnumText(TextNode),
+    EnumVariable(VariableNode),
+    EnumWith(WithNode),
 }
 
 pub trait Node{
+    // 和 Go 一样输出能重新解析的模板源码
     fn string(&self) -> String;
-    fn copy(&self) -> Box<NodeType>;
+    // 深拷贝
+    fn copy(&self) -> NodeType;
     fn position(&self) -> Pos;
-    fn tree(&self) -> CellTree;
 }
 
+impl NodeType{
+    fn as_node(&self) -> &dyn Node{
+        match *self{
+            NodeType::EnumAction(ref n) => n,
+            NodeType::EnumBool(ref n) => n,
+            NodeType::EnumBreak(ref n) => n,
+            NodeType::EnumChain(ref n) => n,
+            NodeType::EnumCommand(ref n) => n,
+            NodeType::EnumComment(ref n) => n,
+            NodeType::EnumContinue(ref n) => n,
+            NodeType::EnumDot(ref n) => n,
+            NodeType::EnumElse(ref n) => n,
+            NodeType::EnumEnd(ref n) => n,
+            NodeType::EnumField(ref n) => n,
+            NodeType::EnumIdentifier(ref n) => n,
+            NodeType::EnumIf(ref n) => n,
+            NodeType::EnumList(ref n) => n,
+            NodeType::EnumNil(ref n) => n,
+            NodeType::EnumNumber(ref n) => n,
+            NodeType::EnumPipe(ref n) => n,
+            NodeType::EnumRange(ref n) => n,
+            NodeType::EnumString(ref n) => n,
+            NodeType::EnumTemplate(ref n) => n,
+            NodeType::EnumText(ref n) => n,
+            NodeType::EnumVariable(ref n) => n,
+            NodeType::EnumWith(ref n) => n,
+        }
+    }
+}
+
+impl Node for NodeType{
+    fn string(&self) -> String{
+        self.as_node().string()
+    }
 
-// #[derive(Debug)]
+    fn copy(&self) -> NodeType{
+        self.as_node().copy()
+    }
+
+    fn position(&self) -> Pos{
+        self.as_node().position()
+    }
+}
+
+// 一串节点，模板的根节点以及 if、range 等的主体
+#[derive(Debug, Clone, PartialEq)]
 pub struct ListNode{
     pub pos: Pos,
-    pub tr: CellTree,
-    pub nodes: Vec<Box<NodeType>>
+    pub nodes: Vec<NodeType>
 }
 
-impl Node for ListNode{
+impl ListNode{
+    pub fn new(pos: Pos) -> ListNode{
+        ListNode{
+            pos,
+            nodes: vec![]
+        }
+    }
+
+    pub fn append(&mut self, node: NodeType){
+        self.nodes.push(node);
+    }
+
+    pub fn copy_list(&self) -> ListNode{
+        let mut ln = ListNode::new(self.pos);
+        for n in &self.nodes{
+            ln.append(n.copy());
+        }
+        ln
+    }
+}
 
-    fn string(&self)-> String{
+impl Node for ListNode{
+    fn string(&self) -> String{
         let mut s = String::new();
         for n in &self.nodes{
-            let f = |n:&Node|->String{
-                n.string()
-            };
-            match_node(n, f);
+            s.push_str(&n.string());
         }
-        return s;
+        s
     }
 
-    fn tree(&self) -> CellTree{
-        return self.tr.clone();
+    fn copy(&self) -> NodeType{
+        NodeType::EnumList(self.copy_list())
     }
 
-    fn copy(&self) -> Box<NodeType>{
-        Box::new(NodeType::EnumList(&self.copy_list()))
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 模板里的纯文本
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextNode{
+    pub pos: Pos,
+    pub text: String
+}
+
+impl TextNode{
+    pub fn new(pos: Pos, text: &str) -> TextNode{
+        TextNode{
+            pos,
+            text: String::from(text)
+        }
+    }
+}
+
+impl Node for TextNode{
+    fn string(&self) -> String{
+        self.text.to_string()
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumText(self.clone())
     }
 
     fn position(&self) -> Pos{
         self.pos
     }
-    
 }
-impl ListNode{
-    fn new(tree: CellTree, pos: Pos) -> Box<ListNode>{
-        let ln = ListNode{
-            pos: pos,
-            tr: tree,
-            nodes: vec![]
+
+// 注释，text 包括注释符号，只有保留注释时才会出现在树里
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommentNode{
+    pub pos: Pos,
+    pub text: String
+}
+
+impl CommentNode{
+    pub fn new(pos: Pos, text: &str) -> CommentNode{
+        CommentNode{
+            pos,
+            text: String::from(text)
+        }
+    }
+}
+
+impl Node for CommentNode{
+    fn string(&self) -> String{
+        format!("{{{{{}}}}}", self.text)
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumComment(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 由 | 连接起来的命令，前面可能有变量声明或者赋值: $x, $y := a | b
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipeNode{
+    pub pos: Pos,
+    pub line: usize,
+    pub is_assign: bool,           // 是 = 而不是 :=
+    pub decl: Vec<VariableNode>,
+    pub cmds: Vec<CommandNode>
+}
+
+impl PipeNode{
+    pub fn new(pos: Pos, line: usize, decl: Vec<VariableNode>) -> PipeNode{
+        PipeNode{
+            pos,
+            line,
+            is_assign: false,
+            decl,
+            cmds: vec![]
+        }
+    }
+
+    pub fn append(&mut self, command: CommandNode){
+        self.cmds.push(command);
+    }
+
+    pub fn copy_pipe(&self) -> PipeNode{
+        self.clone()
+    }
+}
+
+impl Node for PipeNode{
+    fn string(&self) -> String{
+        let mut s = String::new();
+        if !self.decl.is_empty(){
+            for (i, v) in self.decl.iter().enumerate(){
+                if i > 0{
+                    s.push_str(", ");
+                }
+                s.push_str(&v.string());
+            }
+            if self.is_assign{
+                s.push_str(" = ");
+            }else{
+                s.push_str(" := ");
+            }
+        }
+        for (i, c) in self.cmds.iter().enumerate(){
+            if i > 0{
+                s.push_str(" | ");
+            }
+            s.push_str(&c.string());
+        }
+        s
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumPipe(self.copy_pipe())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 不是控制结构的 action，如 {{.X}}
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionNode{
+    pub pos: Pos,
+    pub line: usize,
+    pub pipe: Box<PipeNode>
+}
+
+impl ActionNode{
+    pub fn new(pos: Pos, line: usize, pipe: PipeNode) -> ActionNode{
+        ActionNode{
+            pos,
+            line,
+            pipe: Box::new(pipe)
+        }
+    }
+}
+
+impl Node for ActionNode{
+    fn string(&self) -> String{
+        format!("{{{{{}}}}}", self.pipe.string())
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumAction(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 一个命令，如 printf "%d" 3，参数可以是任意的操作数
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandNode{
+    pub pos: Pos,
+    pub args: Vec<NodeType>
+}
+
+impl CommandNode{
+    pub fn new(pos: Pos) -> CommandNode{
+        CommandNode{
+            pos,
+            args: vec![]
+        }
+    }
+
+    pub fn append(&mut self, arg: NodeType){
+        self.args.push(arg);
+    }
+}
+
+impl Node for CommandNode{
+    fn string(&self) -> String{
+        let mut s = String::new();
+        for (i, arg) in self.args.iter().enumerate(){
+            if i > 0{
+                s.push(' ');
+            }
+            match *arg{
+                // 作为参数的 pipeline 需要加上括号
+                NodeType::EnumPipe(ref p) => {
+                    s.push('(');
+                    s.push_str(&p.string());
+                    s.push(')');
+                },
+                _ => s.push_str(&arg.string()),
+            }
+        }
+        s
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumCommand(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 函数名
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentifierNode{
+    pub pos: Pos,
+    pub ident: String
+}
+
+impl IdentifierNode{
+    pub fn new(pos: Pos, ident: &str) -> IdentifierNode{
+        IdentifierNode{
+            pos,
+            ident: String::from(ident)
+        }
+    }
+}
+
+impl Node for IdentifierNode{
+    fn string(&self) -> String{
+        self.ident.to_string()
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumIdentifier(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 变量，可能带有字段: $x.a.b 的 ident 是 ["$x", "a", "b"]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableNode{
+    pub pos: Pos,
+    pub ident: Vec<String>
+}
+
+impl VariableNode{
+    pub fn new(pos: Pos, ident: &str) -> VariableNode{
+        VariableNode{
+            pos,
+            ident: ident.split('.').map(String::from).collect()
+        }
+    }
+}
+
+impl Node for VariableNode{
+    fn string(&self) -> String{
+        self.ident.join(".")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumVariable(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 光秃秃的 .
+#[derive(Debug, Clone, PartialEq)]
+pub struct DotNode{
+    pub pos: Pos
+}
+
+impl DotNode{
+    pub fn new(pos: Pos) -> DotNode{
+        DotNode{pos}
+    }
+}
+
+impl Node for DotNode{
+    fn string(&self) -> String{
+        String::from(".")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumDot(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NilNode{
+    pub pos: Pos
+}
+
+impl NilNode{
+    pub fn new(pos: Pos) -> NilNode{
+        NilNode{pos}
+    }
+}
+
+impl Node for NilNode{
+    fn string(&self) -> String{
+        String::from("nil")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumNil(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 字段，.a.b 的 ident 是 ["a", "b"]，不包括开头的 .
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldNode{
+    pub pos: Pos,
+    pub ident: Vec<String>
+}
+
+impl FieldNode{
+    pub fn new(pos: Pos, ident: &str) -> FieldNode{
+        FieldNode{
+            pos,
+            ident: ident[1..].split('.').map(String::from).collect()
+        }
+    }
+}
+
+impl Node for FieldNode{
+    fn string(&self) -> String{
+        let mut s = String::new();
+        for id in &self.ident{
+            s.push('.');
+            s.push_str(id);
+        }
+        s
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumField(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 在别的操作数后面接字段，如 (.X).Y 或者 $x.Field
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainNode{
+    pub pos: Pos,
+    pub node: Box<NodeType>,
+    pub field: Vec<String>     // 不包括开头的 .
+}
+
+impl ChainNode{
+    pub fn new(pos: Pos, node: NodeType) -> ChainNode{
+        ChainNode{
+            pos,
+            node: Box::new(node),
+            field: vec![]
+        }
+    }
+
+    // field 是词法分析得到的 .X 这样的字段
+    pub fn add(&mut self, field: &str){
+        if !field.starts_with('.'){
+            panic!("no dot in field");
+        }
+        if field.len() == 1{
+            panic!("empty field");
+        }
+        self.field.push(String::from(&field[1..]));
+    }
+}
+
+impl Node for ChainNode{
+    fn string(&self) -> String{
+        let mut s = match *self.node{
+            NodeType::EnumPipe(ref p) => format!("({})", p.string()),
+            ref n => n.string(),
         };
-        return Box::new(ln);
+        for f in &self.field{
+            s.push('.');
+            s.push_str(f);
+        }
+        s
     }
 
-    fn append(&mut self, node: Box<NodeType>){
-        self.nodes.push(node);
+    fn copy(&self) -> NodeType{
+        NodeType::EnumChain(self.clone())
     }
-    pub fn copy_list(&self) -> Box<ListNode>{
-        let mut ln:Box<ListNode> = ListNode::new(self.tr.clone(), self.pos);
-        for n in &self.nodes{
-            // let n1 = n.copy();
-            // ln.append(n1);
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoolNode{
+    pub pos: Pos,
+    pub val: bool
+}
+
+impl BoolNode{
+    pub fn new(pos: Pos, val: bool) -> BoolNode{
+        BoolNode{
+            pos,
+            val
         }
-        ln
     }
 }
 
-// #[derive(Debug)]
-// pub struct TextNode{
-//     pub pos: Pos,
-//     pub tr: CellTree,
-//     pub text: String
-// }
-
-// impl TextNode{
-//     fn new(tree: CellTree,  pos: Pos, text: &str) -> Box<TextNode>{
-//         let tn = TextNode{
-//             pos: pos,
-//             tr: tree,
-//             text: String::from(text)
-//         };
-//         Box::new(tn)
-//     }
-// }
-
-// impl Node for TextNode{
-//     fn string(&self) -> String{
-//         format!("{}", self.text)
-//     }
-
-//     fn tree(&self) -> CellTree{
-//         self.tr.clone()
-//     }
-
-//     fn copy(&self) -> Box<Node>{
-//         let t = {
-//             TextNode{
-//                 tr: self.tr.clone(),
-//                 pos: self.pos,
-//                 text: format!("{}", self.text)
-//             }
-//         };
-//         Box::new(t)
-//     }
-
-//     fn position(&self) -> Pos{
-//         self.pos
-//     }
-// }
-
-// // #[derive(Debug)]
-// pub struct PipeNode{
-//     pub node_type: NodeType,
-//     pub pos: Pos,
-//     pub tr: CellTree,
-//     pub line: i32,
-//     pub decl: Vec<Box<VariableNode>>, // all is VariableNode
-//     pub cmds: Vec<Box<CommandNode>>  // all is CommandNode
-// }
-
-// impl PipeNode{
-//     fn new(tree: CellTree, pos: Pos, line: i32, decl: Vec<Box<VariableNode>>) -> Box<PipeNode>{
-//         let pn = PipeNode{
-//             node_type: NodeType::NodePipe,
-//             pos: pos,
-//             tr: tree,
-//             line: line,
-//             decl: decl,
-//             cmds: vec![]
-//         };
-//         Box::new(pn)
-//     }
-
-//     fn copy_pipe(&self) -> Box<PipeNode>{
-//         let mut decl:Vec<Box<VariableNode>> = Vec::new();
-//         for n in &self.decl{
-//             let n1: Box<Any> = unsafe{mem::transmute(n.copy())};
-//             if let Ok(n2) = n1.downcast::<VariableNode>(){
-//                 decl.push(n2);
-//             }else{
-//                 panic!("Error type , must be VariableNode");
-//             }
-//         }
-//         let mut cmds:Vec<Box<CommandNode>> = Vec::new();
-//         for n in &self.cmds{
-//             let n1: Box<Any> = unsafe{mem::transmute(n.copy())};
-//             if let Ok(n2) = n1.downcast::<CommandNode>(){
-//                 cmds.push(n2);
-//             }else{
-//                 panic!("Error type, must be CommandNode");
-//             }
-//         }
-//         let n = PipeNode{
-//             tr: self.tr.clone(),
-//             pos: self.pos,
-//             node_type: self.node_type,
-//             line: self.line,
-//             decl: decl,
-//             cmds: cmds
-//         };
-//         Box::new(n)
-//     }
-// }
-
-// impl Node for PipeNode{
-//     fn string(&self) -> String{
-//         let mut s = String::new();
-//         if self.decl.len() > 0{
-//             for i in 0..self.decl.len(){
-//                 if i > 0{
-//                     s.push_str(", ");
-//                 }
-//                 let d = self.decl.get(i).unwrap();
-//                 s.push_str(&d.string());
-//             }
-//             s.push_str(" :=");
-//         }
-//         for i in 0..self.cmds.len(){
-//             if i > 0{
-//                 s.push_str(" | ");
-//             }
-//             let n = self.cmds.get(i).unwrap();
-//             s.push_str(&n.string());
-//         }
-//         s
-//     }
-    
-//     fn tree(&self) -> CellTree{
-//         self.tr.clone()
-//     }
-
-//     fn copy(&self) -> Box<Node>{
-//         self.copy_pipe()
-//     }
-
-//     fn Type(&self) -> NodeType{
-//         self.node_type
-//     }
-
-//     fn position(&self) -> Pos{
-//         self.pos
-//     }
-// }
-
-// // #[derive(Debug)]
-// pub struct VariableNode{
-//     pub node_type: NodeType,
-//     pub pos: Pos,
-//     pub tr: CellTree,
-//     pub ident: Vec<String>
-// }
-
-// impl VariableNode{
-//     fn new(tree: CellTree, pos: Pos, ident: &str) -> Box<VariableNode>{
-//         let vn = VariableNode{
-//             node_type: NodeType::NodeVariable,
-//             pos: pos,
-//             tr: tree,
-//             ident: ident.split(".").map(|s: &str|->String{String::from(s)}).collect()
-//         };
-//         Box::new(vn)
-//     }
-// }
-
-// impl Node for VariableNode{
-//     fn tree(&self) -> CellTree{
-//         self.tr.clone()
-//     }
-
-//     fn Type(&self) -> NodeType{
-//         self.node_type
-//     }
-
-//     fn position(&self) -> Pos{
-//         self.pos
-//     }
-
-//     fn string(&self) -> String{
-//         let mut s = String::new();
-//         for i in 0..self.ident.len(){
-//             if i > 0{
-//                 s.push_str(".");
-//             }
-//             match self.ident.get(i){
-//                 Some(r) => s.push_str(&r),
-//                 _ => ()
-//             }
-//         }
-//         s
-//     }
-
-//     fn copy(&self) -> Box<Node>{
-//         let mut v: Vec<String> = Vec::new();
-//         for n in &self.ident{
-//             v.push(format!("{}", n));
-//         }
-//         let n = VariableNode{
-//             tr: self.tr.clone(),
-//             node_type: self.node_type,
-//             pos: self.pos,
-//             ident: v
-//         };
-//         Box::new(n)
-//     }
-// }
-
-// // #[derive(Debug)]
-// pub struct CommandNode{
-//     pub node_type: NodeType,
-//     pub pos: Pos,
-//     pub tr: CellTree,
-//     pub args: Vec<Box<Node>>
-// }
-
-// impl CommandNode{
-//     fn new(tree: CellTree, pos: Pos) -> Box<CommandNode>{
-//         let cn = CommandNode{
-//             node_type: NodeType::NodeCommand,
-//             pos: pos,
-//             tr: tree,
-//             args: vec![]
-//         };
-//         Box::new(cn)
-//     }
-// }
-// impl Node for CommandNode{
-//     fn string(&self) -> String{
-//         let mut s = String::new();
-//         for i in 0..self.args.len(){
-//             if i > 0 {
-//                 s.push_str(" ");
-//             }
-//             match self.args.get(i){
-//                 Some(node) =>{
-//                     let node1 = node as &Any;
-//                     match node1.downcast_ref::<PipeNode>(){
-//                         Some(n) => {
-//                             s.push_str("(");
-//                             s.push_str(&n.string());
-//                             s.push_str(")");
-//                         },
-//                         None => {
-//                             s.push_str(&node.string());
-//                         }
-//                     }
-//                 },
-//                 _ => ()
-//             }
-//         }
-//         s
-//     }
-
-//     fn tree(&self) -> CellTree{
-//         self.tr.clone()
-//     }
-
-//     fn copy(&self) -> Box<Node>{
-//         let mut nodes: Vec<Box<Node>> = Vec::new();
-//         for n in &self.args{
-//             nodes.push(n.copy());
-//         }
-//         let cnd = CommandNode{
-//             node_type: self.node_type,
-//             pos: self.pos,
-//             tr: self.tr.clone(),
-//             args: nodes 
-//         };
-//         Box::new(cnd)
-//     }
-
-//     fn Type(&self) -> NodeType{
-//         self.node_type 
-//     }
-
-//     fn position(&self) -> Pos{
-//         self.pos
-//     }
-// }
-
-// // #[derive(Debug)]
-// struct ActionNode{
-//     node_type: NodeType,
-//     pos: Pos,
-//     tr: CellTree,
-//     line: i32,
-//     pipe: Box<PipeNode>
-// }
-
-// impl ActionNode{
-//     fn new(tree: CellTree, pos: Pos, line: i32, pipe: Box<PipeNode>) -> Box<ActionNode>{
-//         let ac = ActionNode{
-//             tr: tree,
-//             pos: pos,
-//             line: line,
-//             node_type: NodeType::NodeAction,
-//             pipe: pipe
-//         };
-//         Box::new(ac)
-//     }
-// }
-
-// impl  Node for ActionNode{
-//     fn string(&self) -> String{
-//         format!("{{{{ {} }}}}", self.pipe.string())
-//     }
-
-//     fn copy(&self) -> Box<Node>{
-//         // let ac = ActionNode{
-//         //     tr: self.tr.clone(),
-//         //     pos: self.pos,
-//         //     line: self.line,
-//         //     node_type: NodeType::NodeAction,
-//         //     pipe: &self.pipe.copy_pipe()
-//         // };
-//         // Box::new(ac)
-//         ActionNode::new(self.tr.clone(), self.pos, self.line, self.pipe.copy_pipe())
-//     }
-
-//     fn tree(&self) -> CellTree{
-//         return self.tr.clone();
-//     }
-
-//     fn Type(&self) -> NodeType{
-//         self.node_type
-//     }
-
-//     fn position(&self) -> Pos{
-//         self.pos
-//     }
-    
-// }
+impl Node for BoolNode{
+    fn string(&self) -> String{
+        format!("{}", self.val)
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumBool(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 数字常量，text 是源码里的写法
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberNode{
+    pub pos: Pos,
+    pub number: Number,
+    pub text: String
+}
+
+impl NumberNode{
+    // typ 是 ItemNumber、ItemComplex 或 ItemCharConstant
+    pub fn new(pos: Pos, text: &str, typ: ItemType) -> Result<NumberNode, String>{
+        let number = parse_number(text, typ)?;
+        Ok(NumberNode{
+            pos,
+            number,
+            text: String::from(text)
+        })
+    }
+}
+
+impl Node for NumberNode{
+    fn string(&self) -> String{
+        self.text.to_string()
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumNumber(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// 字符串常量，quoted 是带引号的源码，text 是解码后的值。
+// 和 Go 的字符串一样 text 是字节，"\xff" 这样的转义可以得到不合法的 UTF-8
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringNode{
+    pub pos: Pos,
+    pub quoted: String,
+    pub text: Vec<u8>
+}
+
+impl StringNode{
+    pub fn new(pos: Pos, quoted: &str, text: &[u8]) -> StringNode{
+        StringNode{
+            pos,
+            quoted: String::from(quoted),
+            text: text.to_vec()
+        }
+    }
+}
+
+impl Node for StringNode{
+    fn string(&self) -> String{
+        self.quoted.to_string()
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumString(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// {{end}}，只在解析过程中出现，不会留在最终的树里
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndNode{
+    pub pos: Pos
+}
+
+impl EndNode{
+    pub fn new(pos: Pos) -> EndNode{
+        EndNode{pos}
+    }
+}
+
+impl Node for EndNode{
+    fn string(&self) -> String{
+        String::from("{{end}}")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumEnd(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// {{else}}，和 EndNode 一样只在解析过程中出现
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElseNode{
+    pub pos: Pos,
+    pub line: usize
+}
+
+impl ElseNode{
+    pub fn new(pos: Pos, line: usize) -> ElseNode{
+        ElseNode{
+            pos,
+            line
+        }
+    }
+}
+
+impl Node for ElseNode{
+    fn string(&self) -> String{
+        String::from("{{else}}")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumElse(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakNode{
+    pub pos: Pos,
+    pub line: usize
+}
+
+impl BreakNode{
+    pub fn new(pos: Pos, line: usize) -> BreakNode{
+        BreakNode{
+            pos,
+            line
+        }
+    }
+}
+
+impl Node for BreakNode{
+    fn string(&self) -> String{
+        String::from("{{break}}")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumBreak(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContinueNode{
+    pub pos: Pos,
+    pub line: usize
+}
+
+impl ContinueNode{
+    pub fn new(pos: Pos, line: usize) -> ContinueNode{
+        ContinueNode{
+            pos,
+            line
+        }
+    }
+}
+
+impl Node for ContinueNode{
+    fn string(&self) -> String{
+        String::from("{{continue}}")
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumContinue(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// if、range、with 的结构都一样: {{name pipe}} list {{else}} else_list {{end}}
+fn branch_string(name: &str, pipe: &PipeNode, list: &ListNode, else_list: &Option<Box<ListNode>>) -> String{
+    let mut s = format!("{{{{{} {}}}}}{}", name, pipe.string(), list.string());
+    if let Some(ref l) = *else_list{
+        s.push_str("{{else}}");
+        s.push_str(&l.string());
+    }
+    s.push_str("{{end}}");
+    s
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfNode{
+    pub pos: Pos,
+    pub line: usize,
+    pub pipe: Box<PipeNode>,
+    pub list: Box<ListNode>,
+    pub else_list: Option<Box<ListNode>>
+}
+
+impl IfNode{
+    pub fn new(pos: Pos, line: usize, pipe: PipeNode, list: ListNode, else_list: Option<ListNode>) -> IfNode{
+        IfNode{
+            pos,
+            line,
+            pipe: Box::new(pipe),
+            list: Box::new(list),
+            else_list: else_list.map(Box::new)
+        }
+    }
+}
+
+impl Node for IfNode{
+    fn string(&self) -> String{
+        branch_string("if", &self.pipe, &self.list, &self.else_list)
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumIf(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeNode{
+    pub pos: Pos,
+    pub line: usize,
+    pub pipe: Box<PipeNode>,
+    pub list: Box<ListNode>,
+    pub else_list: Option<Box<ListNode>>
+}
+
+impl RangeNode{
+    pub fn new(pos: Pos, line: usize, pipe: PipeNode, list: ListNode, else_list: Option<ListNode>) -> RangeNode{
+        RangeNode{
+            pos,
+            line,
+            pipe: Box::new(pipe),
+            list: Box::new(list),
+            else_list: else_list.map(Box::new)
+        }
+    }
+}
+
+impl Node for RangeNode{
+    fn string(&self) -> String{
+        branch_string("range", &self.pipe, &self.list, &self.else_list)
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumRange(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithNode{
+    pub pos: Pos,
+    pub line: usize,
+    pub pipe: Box<PipeNode>,
+    pub list: Box<ListNode>,
+    pub else_list: Option<Box<ListNode>>
+}
+
+impl WithNode{
+    pub fn new(pos: Pos, line: usize, pipe: PipeNode, list: ListNode, else_list: Option<ListNode>) -> WithNode{
+        WithNode{
+            pos,
+            line,
+            pipe: Box::new(pipe),
+            list: Box::new(list),
+            else_list: else_list.map(Box::new)
+        }
+    }
+}
+
+impl Node for WithNode{
+    fn string(&self) -> String{
+        branch_string("with", &self.pipe, &self.list, &self.else_list)
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumWith(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
+
+// {{template "name" pipe}}，pipe 可以没有
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateNode{
+    pub pos: Pos,
+    pub line: usize,
+    pub name: String,
+    pub pipe: Option<Box<PipeNode>>
+}
+
+impl TemplateNode{
+    pub fn new(pos: Pos, line: usize, name: &str, pipe: Option<PipeNode>) -> TemplateNode{
+        TemplateNode{
+            pos,
+            line,
+            name: String::from(name),
+            pipe: pipe.map(Box::new)
+        }
+    }
+}
+
+impl Node for TemplateNode{
+    fn string(&self) -> String{
+        match self.pipe{
+            None => format!("{{{{template {}}}}}", quote(&self.name)),
+            Some(ref p) => format!("{{{{template {} {}}}}}", quote(&self.name), p.string()),
+        }
+    }
+
+    fn copy(&self) -> NodeType{
+        NodeType::EnumTemplate(self.clone())
+    }
+
+    fn position(&self) -> Pos{
+        self.pos
+    }
+}
@@ -0,0 +1,85 @@
+use super::lex::ItemType;
+use super::node::*;
+
+fn pipe(cmds: Vec<Vec<NodeType>>) -> PipeNode{
+    let mut p = PipeNode::new(0, 1, vec![]);
+    for args in cmds{
+        let mut c = CommandNode::new(0);
+        for a in args{
+            c.append(a);
+        }
+        p.append(c);
+    }
+    p
+}
+
+fn text(s: &str) -> NodeType{
+    NodeType::EnumText(TextNode::new(0, s))
+}
+
+#[test]
+fn test_node_string(){
+    let field = NodeType::EnumField(FieldNode::new(3, ".X.Y"));
+    let number = NodeType::EnumNumber(NumberNode::new(7, "0x10", ItemType::ItemNumber).unwrap());
+    let string = NodeType::EnumString(StringNode::new(9, "`a`", b"a"));
+    let ident = NodeType::EnumIdentifier(IdentifierNode::new(2, "printf"));
+    let mut decl = pipe(vec![vec![NodeType::EnumDot(DotNode::new(0))]]);
+    decl.decl.push(VariableNode::new(0, "$x"));
+    let mut chain = ChainNode::new(1, NodeType::EnumPipe(pipe(vec![vec![field.clone()]])));
+    chain.add(".Z");
+    let tests = vec![
+        (text("hello"), "hello"),
+        (NodeType::EnumComment(CommentNode::new(0, "/* c */")), "{{/* c */}}"),
+        (field.clone(), ".X.Y"),
+        (NodeType::EnumVariable(VariableNode::new(0, "$x.a.b")), "$x.a.b"),
+        (NodeType::EnumBool(BoolNode::new(0, true)), "true"),
+        (NodeType::EnumNil(NilNode::new(0)), "nil"),
+        (number.clone(), "0x10"),
+        (string.clone(), "`a`"),
+        (NodeType::EnumChain(chain), "(.X.Y).Z"),
+        (NodeType::EnumPipe(decl.clone()), "$x := ."),
+        (NodeType::EnumAction(ActionNode::new(0, 1, pipe(vec![vec![ident.clone(), number.clone(), NodeType::EnumPipe(pipe(vec![vec![field.clone()]]))], vec![string.clone()]]))),
+            "{{printf 0x10 (.X.Y) | `a`}}"),
+        (NodeType::EnumIf(IfNode::new(0, 1, pipe(vec![vec![field.clone()]]), ListNode::new(0), None)), "{{if .X.Y}}{{end}}"),
+        (NodeType::EnumRange(RangeNode::new(0, 1, decl.clone(), ListNode{pos: 0, nodes: vec![text("a")]}, Some(ListNode{pos: 0, nodes: vec![text("b")]}))),
+            "{{range $x := .}}a{{else}}b{{end}}"),
+        (NodeType::EnumWith(WithNode::new(0, 1, pipe(vec![vec![field.clone()]]), ListNode::new(0), None)), "{{with .X.Y}}{{end}}"),
+        (NodeType::EnumTemplate(TemplateNode::new(0, 1, "x\"y", None)), r#"{{template "x\"y"}}"#),
+        (NodeType::EnumTemplate(TemplateNode::new(0, 1, "x", Some(pipe(vec![vec![field.clone()]])))), r#"{{template "x" .X.Y}}"#),
+        (NodeType::EnumBreak(BreakNode::new(0, 1)), "{{break}}"),
+        (NodeType::EnumContinue(ContinueNode::new(0, 1)), "{{continue}}"),
+    ];
+    for (node, expected) in tests{
+        assert_eq!(node.string(), expected);
+    }
+}
+
+#[test]
+fn test_node_copy(){
+    let mut list = ListNode::new(5);
+    list.append(text("a"));
+    list.append(NodeType::EnumIf(IfNode::new(6, 1, pipe(vec![vec![NodeType::EnumDot(DotNode::new(10))]]), ListNode::new(12), None)));
+    let copy = list.copy();
+    assert_eq!(copy.position(), 5);
+    assert_eq!(copy.string(), "a{{if .}}{{end}}");
+    // 拷贝是独立的，修改原来的树不影响拷贝
+    list.append(text("b"));
+    match copy{
+        NodeType::EnumList(ref l) => {
+            assert_eq!(l.nodes.len(), 2);
+            assert_eq!(l.nodes[1].position(), 6);
+        },
+        _ => panic!("copy of a list must be a list"),
+    }
+    assert_eq!(list.string(), "a{{if .}}{{end}}b");
+}
+
+#[test]
+fn test_number_node(){
+    let n = NumberNode::new(0, "1e3", ItemType::ItemNumber).unwrap();
+    assert!(n.number.is_int && n.number.is_float);
+    assert_eq!(n.number.int64, 1000);
+    let c = NumberNode::new(0, "'a'", ItemType::ItemCharConstant).unwrap();
+    assert_eq!(c.number.int64, 97);
+    assert_eq!(NumberNode::new(0, "1e400", ItemType::ItemNumber).unwrap_err(), "illegal number syntax: \"1e400\"");
+}
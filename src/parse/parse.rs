@@ -1,35 +1,755 @@
 use super::node:: *;
 use super::lex::*;
-use std::cell::RefCell;
-use std::rc::Rc;
+use super::strconv;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
 
-// #[derive(Debug)]
-pub struct Tree{
+// 解析出错的位置和原因，输出的格式和 Go 一样: template: name:line: msg
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError{
     pub name: String,
-    pub parse_name: String,
+    pub pos: Pos,
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "template: {}:{}: {}", self.name, self.line, self.msg)
+    }
+}
+
+// 和 Go 一样，一次解析得到的所有模板: 最外层的模板以及 define、block 定义的子模板
+pub type TreeSet<'a> = HashMap<String, Tree<'a>>;
+
+// 解析模板，返回按名字索引的所有模板
+pub fn parse<'a>(name: &str, text: &'a str, left_delim: &'a str, right_delim: &'a str, funcs: &[&str]) -> Result<TreeSet<'a>, ParseError>{
+    let mut tree_set = TreeSet::new();
+    let mut t = Tree::new(name, funcs);
+    t.parse(text, left_delim, right_delim, &mut tree_set)?;
+    Ok(tree_set)
+}
+
+pub struct Tree<'a>{
+    pub name: String,
+    pub parse_name: String,   // 出错信息里用的名字，子模板里是最外层模板的名字
     pub root: Box<ListNode>,
-    text: String,
-    lex: Option<Box<Lexer>>,
-    token: [Option<Item>; 3],
-    peek_count: i8,
-    vars: Vec<String>
+    // 把注释作为 CommentNode 放进语法树
+    pub parse_comments: bool,
+    // 不检查 identifier 是否是已知的函数
+    pub skip_func_check: bool,
+    text: &'a str,
+    funcs: Vec<String>,
+    // 解析过程中才会用到的状态
+    lex: Option<Box<Lexer<'a>>>,
+    token: [Option<Item<'a>>; 3], // 最多三个 token 的向前看
+    peek_count: usize,
+    vars: Vec<String>,            // 当前可见的变量，每个模板都从 $ 开始，控制结构结束时弹出
+    tree_set: TreeSet<'a>,        // 解析期间从调用者那里借过来，define 的子模板加到这里
+    action_line: usize,           // 正在解析的 action 的起始行，出错信息用
+    range_depth: usize,           // 嵌套的 range 层数，为 0 时不能用 break 和 continue
 }
 
-impl Tree{
-    pub fn copy(&self) ->Box<Tree>{
+impl<'a> Tree<'a>{
+    pub fn new(name: &str, funcs: &[&str]) -> Tree<'a>{
+        Tree{
+            name: name.to_string(),
+            parse_name: name.to_string(),
+            root: Box::new(ListNode::new(0)),
+            parse_comments: false,
+            skip_func_check: false,
+            text: "",
+            funcs: funcs.iter().map(|f| f.to_string()).collect(),
+            lex: None,
+            token: [None, None, None],
+            peek_count: 0,
+            vars: vec![],
+            tree_set: TreeSet::new(),
+            action_line: 0,
+            range_depth: 0
+        }
+    }
+
+    pub fn copy(&self) ->Box<Tree<'a>>{
         let tree = Tree{
-            name: format!("{}", self.name),
-            parse_name: format!("{}", self.name),
-            root: self.root.copy(),
-            text: format!("{}", self.text),
+            name: self.name.to_string(),
+            parse_name: self.parse_name.to_string(),
+            root: Box::new(self.root.copy_list()),
+            parse_comments: self.parse_comments,
+            skip_func_check: self.skip_func_check,
+            text: self.text,
+            funcs: self.funcs.clone(),
             lex: None,
             token: [None, None, None],
             peek_count: 0,
-            vars: vec![]
+            vars: vec![],
+            tree_set: TreeSet::new(),
+            action_line: 0,
+            range_depth: 0
         };
         Box::new(tree)
     }
+
+    // 解析模板，出错时 root 保持为空。
+    // 模板里 define 的子模板都加到 tree_set 中，解析成功后模板自己的一份拷贝也会加进去，
+    // 同名的模板只有在原来的是空树时才会被替换
+    pub fn parse(&mut self, text: &'a str, left_delim: &'a str, right_delim: &'a str, tree_set: &mut TreeSet<'a>) -> Result<(), ParseError>{
+        // 词法分析器的名字只在调试时有用，出错信息用的是 parse_name
+        let lexer = LexerOptions::new()
+            .delims(left_delim, right_delim)
+            .emit_comments(self.parse_comments)
+            .lex("", text);
+        self.text = text;
+        self.start_parse(Some(Box::new(lexer)), mem::take(tree_set));
+        let result = match self.parse_root(){
+            Ok(root) => {
+                *self.root = root;
+                self.check_add()
+            }
+            Err(err) => Err(err)
+        };
+        *tree_set = mem::take(&mut self.tree_set);
+        self.stop_parse();
+        match result{
+            Ok(add) => {
+                if add{
+                    tree_set.insert(self.name.clone(), *self.copy());
+                }
+                Ok(())
+            }
+            Err(err) => {
+                *self.root = ListNode::new(0);
+                Err(err)
+            }
+        }
+    }
+
+    // 返回节点在源码中的位置 name:line:col 和节点的源码，用于执行时的出错信息
+    pub fn error_context(&self, node: &dyn Node) -> (String, String){
+        let pos = node.position();
+        let text = &self.text[..pos.min(self.text.len())];
+        let byte_num = match text.rfind('\n'){
+            Some(i) => pos - i - 1,
+            None => pos
+        };
+        let line_num = 1 + text.matches('\n').count();
+        let mut context = node.string();
+        if context.len() > 20{
+            let head: String = context.chars().take(20).collect();
+            context = format!("{}...", head);
+        }
+        (format!("{}:{}:{}", self.parse_name, line_num, byte_num), context)
+    }
+
+    fn start_parse(&mut self, lexer: Option<Box<Lexer<'a>>>, tree_set: TreeSet<'a>){
+        self.lex = lexer;
+        self.tree_set = tree_set;
+        self.token = [None, None, None];
+        self.peek_count = 0;
+        self.vars = vec!["$".to_string()];
+        self.action_line = 0;
+        self.range_depth = 0;
+    }
+
+    fn stop_parse(&mut self){
+        self.lex = None;
+        self.token = [None, None, None];
+        self.peek_count = 0;
+        self.vars = vec![];
+    }
+
+    // 从词法分析器取下一个 token，结束以后一直返回 EOF
+    fn lex_item(&mut self) -> Item<'a>{
+        if let Some(item) = self.lex.as_mut().and_then(|l| l.next_item()){
+            return item;
+        }
+        let (line, col) = match self.token[0]{
+            Some(ref t) => (t.line, t.col),
+            None => (1, 1)
+        };
+        Item{
+            typ: ItemType::ItemEOF,
+            pos: self.text.len(),
+            val: Cow::Borrowed(""),
+            line,
+            col,
+        }
+    }
+
+    fn next(&mut self) -> Item<'a>{
+        if self.peek_count > 0{
+            self.peek_count -= 1;
+        }else{
+            self.token[0] = Some(self.lex_item());
+        }
+        self.token[self.peek_count].clone().unwrap()
+    }
+
+    // 退回一个 token
+    fn backup(&mut self){
+        self.peek_count += 1;
+    }
+
+    // 退回两个 token，token[0] 已经在里面了
+    fn backup2(&mut self, t1: Item<'a>){
+        self.token[1] = Some(t1);
+        self.peek_count = 2;
+    }
+
+    // 退回三个 token，token[0] 已经在里面了
+    fn backup3(&mut self, t2: Item<'a>, t1: Item<'a>){
+        self.token[1] = Some(t1);
+        self.token[2] = Some(t2);
+        self.peek_count = 3;
+    }
+
+    fn peek(&mut self) -> Item<'a>{
+        if self.peek_count > 0{
+            return self.token[self.peek_count - 1].clone().unwrap();
+        }
+        self.peek_count = 1;
+        self.token[0] = Some(self.lex_item());
+        self.token[0].clone().unwrap()
+    }
+
+    fn next_non_space(&mut self) -> Item<'a>{
+        loop{
+            let token = self.next();
+            if token.typ != ItemType::ItemSpace{
+                return token;
+            }
+        }
+    }
+
+    fn peek_non_space(&mut self) -> Item<'a>{
+        let token = self.next_non_space();
+        self.backup();
+        token
+    }
+
+    // 出错的行号和 Go 一样取最近一个从词法分析器读到的 token
+    fn errorf(&self, msg: String) -> ParseError{
+        let (pos, line, col) = match self.token[0]{
+            Some(ref t) => (t.pos, t.line, t.col),
+            None => (0, 1, 1)
+        };
+        ParseError{
+            name: self.parse_name.clone(),
+            pos,
+            line,
+            col,
+            msg,
+        }
+    }
+
+    // 在指定 token 的位置报错，不是最后读到的 token
+    fn error_at(&self, token: &Item<'a>, msg: String) -> ParseError{
+        ParseError{
+            name: self.parse_name.clone(),
+            pos: token.pos,
+            line: token.line,
+            col: token.col,
+            msg,
+        }
+    }
+
+    fn unexpected(&self, token: &Item<'a>, context: &str) -> ParseError{
+        if token.typ == ItemType::ItemError{
+            let mut extra = String::new();
+            if self.action_line != 0 && self.action_line != token.line{
+                extra = format!(" in action started at {}:{}", self.parse_name, self.action_line);
+                if token.val.ends_with(" action"){
+                    // 避免出现 "action in action"
+                    extra = extra[" in action".len()..].to_string();
+                }
+            }
+            return self.errorf(format!("{}{}", token, extra));
+        }
+        self.errorf(format!("unexpected {} in {}", token, context))
+    }
+
+    // 变量必须在当前或者外层的作用域里声明过，$ 总是可用
+    fn use_var(&self, token: &Item<'a>) -> Result<NodeType, ParseError>{
+        let v = VariableNode::new(token.pos, &token.val);
+        if self.vars.iter().any(|name| *name == v.ident[0]){
+            return Ok(NodeType::EnumVariable(v));
+        }
+        let msg = format!("undefined variable {}", quote(&v.ident[0]));
+        Err(self.error_at(token, msg))
+    }
+
+    fn has_function(&self, name: &str) -> bool{
+        self.funcs.iter().any(|f| f == name)
+    }
+
+    // 模板的顶层: 文本、注释和 action 一直到 EOF，define 只能出现在这一层
+    fn parse_root(&mut self) -> Result<ListNode, ParseError>{
+        let mut root = ListNode::new(self.peek().pos);
+        while self.peek().typ != ItemType::ItemEOF{
+            if self.peek().typ == ItemType::ItemLeftDelim{
+                let delim = self.next();
+                if self.next_non_space().typ == ItemType::ItemDefine{
+                    // 名字要等解析到了才知道
+                    let mut t = self.new_sub_tree("definition");
+                    let result = t.parse_definition();
+                    self.finish_sub_tree(t, result)?;
+                    continue;
+                }
+                self.backup2(delim);
+            }
+            let n = self.text_or_action()?;
+            match n{
+                NodeType::EnumEnd(_) | NodeType::EnumElse(_) => {
+                    return Err(self.errorf(format!("unexpected {}", n.string())));
+                }
+                _ => root.append(n)
+            }
+        }
+        Ok(root)
+    }
+
+    // define 和 block 定义的子模板和当前模板共用词法分析器和 tree_set，
+    // 子模板解析完以后要用 finish_sub_tree 还回来
+    fn new_sub_tree(&mut self, name: &str) -> Tree<'a>{
+        let mut t = Tree::new(name, &[]);
+        t.parse_name = self.parse_name.clone();
+        t.parse_comments = self.parse_comments;
+        t.skip_func_check = self.skip_func_check;
+        t.text = self.text;
+        t.funcs = self.funcs.clone();
+        t.start_parse(self.lex.take(), mem::take(&mut self.tree_set));
+        t
+    }
+
+    // result 是子模板是否应该加入 tree_set
+    fn finish_sub_tree(&mut self, mut t: Tree<'a>, result: Result<bool, ParseError>) -> Result<(), ParseError>{
+        self.lex = t.lex.take();
+        self.tree_set = mem::take(&mut t.tree_set);
+        if result?{
+            t.stop_parse();
+            self.tree_set.insert(t.name.clone(), t);
+        }
+        Ok(())
+    }
+
+    // {{define "name"}} 已经读过了，解析到对应的 {{end}}
+    fn parse_definition(&mut self) -> Result<bool, ParseError>{
+        let context = "define clause";
+        let name = self.expect_one_of(ItemType::ItemString, ItemType::ItemRawString, context)?;
+        self.name = self.parse_template_name(&name, context)?;
+        self.expect(ItemType::ItemRightDelim, context)?;
+        self.parse_sub_tree_body(context)
+    }
+
+    fn parse_sub_tree_body(&mut self, context: &str) -> Result<bool, ParseError>{
+        let (root, end) = self.item_list()?;
+        *self.root = root;
+        if let NodeType::EnumElse(_) = end{
+            return Err(self.errorf(format!("unexpected {} in {}", end.string(), context)));
+        }
+        self.check_add()
+    }
+
+    // Go 的重复定义规则: 还没有同名的模板或者已有的是空树时可以加入（替换），
+    // 已有的不是空树时，新的也不是空树就报错，是空树就直接忽略
+    fn check_add(&self) -> Result<bool, ParseError>{
+        match self.tree_set.get(&self.name){
+            None => return Ok(true),
+            Some(tree) if is_empty_list(&tree.root) => return Ok(true),
+            Some(_) => {}
+        }
+        if !is_empty_list(&self.root){
+            return Err(self.errorf(format!("template: multiple definition of template {}", quote(&self.name))));
+        }
+        Ok(false)
+    }
+
+    // 一直解析到 {{end}} 或者 {{else}}，把它们和前面的节点一起返回
+    fn item_list(&mut self) -> Result<(ListNode, NodeType), ParseError>{
+        let mut list = ListNode::new(self.peek_non_space().pos);
+        while self.peek_non_space().typ != ItemType::ItemEOF{
+            let n = self.text_or_action()?;
+            match n{
+                NodeType::EnumEnd(_) | NodeType::EnumElse(_) => return Ok((list, n)),
+                _ => list.append(n)
+            }
+        }
+        Err(self.errorf(String::from("unexpected EOF")))
+    }
+
+    fn text_or_action(&mut self) -> Result<NodeType, ParseError>{
+        let token = self.next_non_space();
+        match token.typ{
+            ItemType::ItemText => Ok(NodeType::EnumText(TextNode::new(token.pos, &token.val))),
+            ItemType::ItemLeftDelim => {
+                self.action_line = token.line;
+                let n = self.action();
+                self.action_line = 0;
+                n
+            }
+            ItemType::ItemComment => Ok(NodeType::EnumComment(CommentNode::new(token.pos, &token.val))),
+            _ => Err(self.unexpected(&token, "input"))
+        }
+    }
+
+    // 左分隔符已经读过了，先看是不是控制结构
+    fn action(&mut self) -> Result<NodeType, ParseError>{
+        let token = self.next_non_space();
+        match token.typ{
+            ItemType::ItemBlock => return self.block_control(),
+            ItemType::ItemBreak => return self.break_control(token.pos, token.line),
+            ItemType::ItemContinue => return self.continue_control(token.pos, token.line),
+            ItemType::ItemElse => return self.else_control(),
+            ItemType::ItemEnd => return self.end_control(),
+            ItemType::ItemIf => return self.if_control(),
+            ItemType::ItemRange => return self.range_control(),
+            ItemType::ItemTemplate => return self.template_control(),
+            ItemType::ItemWith => return self.with_control(),
+            _ => {}
+        }
+        self.backup();
+        let token = self.peek();
+        let pipe = self.pipeline("command", ItemType::ItemRightDelim)?;
+        Ok(NodeType::EnumAction(ActionNode::new(token.pos, token.line, pipe)))
+    }
+
+    // if、range、with 共用的部分: {{name pipe}} list {{else}} else_list {{end}}，
+    // 返回的 pos 和 line 取自 pipeline
+    fn parse_control(&mut self, context: &str) -> Result<(Pos, usize, PipeNode, ListNode, Option<ListNode>), ParseError>{
+        // pipeline 里声明的变量在 else 里也能用，到 {{end}} 为止
+        let vars = self.vars.len();
+        let result = self.parse_control_scope(context);
+        self.vars.truncate(vars);
+        result
+    }
+
+    fn parse_control_scope(&mut self, context: &str) -> Result<(Pos, usize, PipeNode, ListNode, Option<ListNode>), ParseError>{
+        let pipe = self.pipeline(context, ItemType::ItemRightDelim)?;
+        if context == "range"{
+            self.range_depth += 1;
+        }
+        let result = self.item_list();
+        if context == "range"{
+            self.range_depth -= 1;
+        }
+        let (list, next) = result?;
+        let mut else_list = None;
+        if let NodeType::EnumElse(_) = next{
+            // {{if a}}_{{else if b}}_{{end}} 当作 {{if a}}_{{else}}{{if b}}_{{end}}{{end}} 处理，
+            // with 也一样。else_control 没有读走后面的 if/with，这里照常解析它直到它的 {{end}}，
+            // 外层的 {{end}} 就省略了，所以再长的 else if 链也只需要一个 {{end}}
+            if context == "if" && self.peek().typ == ItemType::ItemIf{
+                self.next();
+                let mut l = ListNode::new(next.position());
+                l.append(self.if_control()?);
+                else_list = Some(l);
+            }else if context == "with" && self.peek().typ == ItemType::ItemWith{
+                self.next();
+                let mut l = ListNode::new(next.position());
+                l.append(self.with_control()?);
+                else_list = Some(l);
+            }else{
+                let (l, next) = self.item_list()?;
+                if let NodeType::EnumEnd(_) = next{
+                    else_list = Some(l);
+                }else{
+                    return Err(self.errorf(format!("expected end; found {}", next.string())));
+                }
+            }
+        }
+        Ok((pipe.pos, pipe.line, pipe, list, else_list))
+    }
+
+    fn if_control(&mut self) -> Result<NodeType, ParseError>{
+        let (pos, line, pipe, list, else_list) = self.parse_control("if")?;
+        Ok(NodeType::EnumIf(IfNode::new(pos, line, pipe, list, else_list)))
+    }
+
+    fn range_control(&mut self) -> Result<NodeType, ParseError>{
+        let (pos, line, pipe, list, else_list) = self.parse_control("range")?;
+        Ok(NodeType::EnumRange(RangeNode::new(pos, line, pipe, list, else_list)))
+    }
+
+    fn with_control(&mut self) -> Result<NodeType, ParseError>{
+        let (pos, line, pipe, list, else_list) = self.parse_control("with")?;
+        Ok(NodeType::EnumWith(WithNode::new(pos, line, pipe, list, else_list)))
+    }
+
+    fn end_control(&mut self) -> Result<NodeType, ParseError>{
+        let token = self.expect(ItemType::ItemRightDelim, "end")?;
+        Ok(NodeType::EnumEnd(EndNode::new(token.pos)))
+    }
+
+    fn else_control(&mut self) -> Result<NodeType, ParseError>{
+        // {{else if ...}} 和 {{else with ...}} 当作 {{else}}{{if ...}} 处理，
+        // 这里只返回 else 节点，把 if/with 留给 parse_control
+        let peek = self.peek_non_space();
+        if peek.typ == ItemType::ItemIf || peek.typ == ItemType::ItemWith{
+            return Ok(NodeType::EnumElse(ElseNode::new(peek.pos, peek.line)));
+        }
+        let token = self.expect(ItemType::ItemRightDelim, "else")?;
+        Ok(NodeType::EnumElse(ElseNode::new(token.pos, token.line)))
+    }
+
+    fn break_control(&mut self, pos: Pos, line: usize) -> Result<NodeType, ParseError>{
+        let token = self.next_non_space();
+        if token.typ != ItemType::ItemRightDelim{
+            return Err(self.unexpected(&token, "{{break}}"));
+        }
+        if self.range_depth == 0{
+            return Err(self.errorf(String::from("{{break}} outside {{range}}")));
+        }
+        Ok(NodeType::EnumBreak(BreakNode::new(pos, line)))
+    }
+
+    fn continue_control(&mut self, pos: Pos, line: usize) -> Result<NodeType, ParseError>{
+        let token = self.next_non_space();
+        if token.typ != ItemType::ItemRightDelim{
+            return Err(self.unexpected(&token, "{{continue}}"));
+        }
+        if self.range_depth == 0{
+            return Err(self.errorf(String::from("{{continue}} outside {{range}}")));
+        }
+        Ok(NodeType::EnumContinue(ContinueNode::new(pos, line)))
+    }
+
+    // {{block "name" pipe}} list {{end}}: 定义名为 name 的模板，同时在这里执行它
+    fn block_control(&mut self) -> Result<NodeType, ParseError>{
+        let context = "block clause";
+        let token = self.next_non_space();
+        let name = self.parse_template_name(&token, context)?;
+        let pipe = self.pipeline(context, ItemType::ItemRightDelim)?;
+        let mut block = self.new_sub_tree(&name);
+        let result = block.parse_sub_tree_body(context);
+        self.finish_sub_tree(block, result)?;
+        Ok(NodeType::EnumTemplate(TemplateNode::new(token.pos, token.line, &name, Some(pipe))))
+    }
+
+    // {{template "name"}} 或者 {{template "name" pipe}}
+    fn template_control(&mut self) -> Result<NodeType, ParseError>{
+        let context = "template clause";
+        let token = self.next_non_space();
+        let name = self.parse_template_name(&token, context)?;
+        let mut pipe = None;
+        if self.next_non_space().typ != ItemType::ItemRightDelim{
+            self.backup();
+            pipe = Some(self.pipeline(context, ItemType::ItemRightDelim)?);
+        }
+        Ok(NodeType::EnumTemplate(TemplateNode::new(token.pos, token.line, &name, pipe)))
+    }
+
+    // 模板名是 TreeSet 的 key，必须是合法的 UTF-8，所以 "\xff" 这样的名字会报错，这一点和 Go 不同
+    fn parse_template_name(&self, token: &Item<'a>, context: &str) -> Result<String, ParseError>{
+        match token.typ{
+            ItemType::ItemString | ItemType::ItemRawString => {
+                match strconv::unquote(&token.val){
+                    Ok(s) => Ok(s),
+                    Err(err) => Err(self.errorf(err.msg))
+                }
+            }
+            _ => Err(self.unexpected(token, context))
+        }
+    }
+
+    fn expect_one_of(&mut self, expected1: ItemType, expected2: ItemType, context: &str) -> Result<Item<'a>, ParseError>{
+        let token = self.next_non_space();
+        if token.typ != expected1 && token.typ != expected2{
+            return Err(self.unexpected(&token, context));
+        }
+        Ok(token)
+    }
+
+    // 下一个非空白的 token 必须是 expected
+    fn expect(&mut self, expected: ItemType, context: &str) -> Result<Item<'a>, ParseError>{
+        let token = self.next_non_space();
+        if token.typ != expected{
+            return Err(self.unexpected(&token, context));
+        }
+        Ok(token)
+    }
+
+    // 解析 pipeline 直到 end，context 用于出错信息
+    fn pipeline(&mut self, context: &str, end: ItemType) -> Result<PipeNode, ParseError>{
+        let token = self.peek_non_space();
+        let mut pipe = PipeNode::new(token.pos, token.line, vec![]);
+        // 开头是否有变量的声明或者赋值
+        loop{
+            let v = self.peek_non_space();
+            if v.typ != ItemType::ItemVariable{
+                break;
+            }
+            self.next();
+            // 空白也是 token，最坏的情况下要向前看三个 token: "$x foo" 要读到 foo
+            // （而不是 :=）才知道 $x 是参数而不是声明，所以先记下紧跟着变量的 token 以便退回
+            let token_after_variable = self.peek();
+            let next = self.peek_non_space();
+            match next.typ{
+                ItemType::ItemAssign | ItemType::ItemColonEquals => {
+                    pipe.is_assign = next.typ == ItemType::ItemAssign;
+                    self.next_non_space();
+                    pipe.decl.push(VariableNode::new(v.pos, &v.val));
+                    self.vars.push(v.val.to_string());
+                }
+                ItemType::ItemChar if next.val == "," => {
+                    self.next_non_space();
+                    pipe.decl.push(VariableNode::new(v.pos, &v.val));
+                    self.vars.push(v.val.to_string());
+                    if context == "range" && pipe.decl.len() < 2{
+                        match self.peek_non_space().typ{
+                            // range 可以声明第二个变量
+                            ItemType::ItemVariable | ItemType::ItemRightDelim | ItemType::ItemRightParen => continue,
+                            _ => return Err(self.errorf(String::from("range can only initialize variables")))
+                        }
+                    }
+                    return Err(self.errorf(format!("too many declarations in {}", context)));
+                }
+                _ if token_after_variable.typ == ItemType::ItemSpace => self.backup3(v, token_after_variable),
+                _ => self.backup2(v)
+            }
+            break;
+        }
+        loop{
+            let token = self.next_non_space();
+            match token.typ{
+                t if t == end => {
+                    // pipeline 结束了
+                    self.check_pipeline(&pipe, context)?;
+                    return Ok(pipe);
+                }
+                ItemType::ItemBool | ItemType::ItemCharConstant | ItemType::ItemComplex |
+                ItemType::ItemDot | ItemType::ItemField | ItemType::ItemIdentifier |
+                ItemType::ItemNumber | ItemType::ItemNil | ItemType::ItemRawString |
+                ItemType::ItemString | ItemType::ItemVariable | ItemType::ItemLeftParen => {
+                    self.backup();
+                    let cmd = self.command()?;
+                    pipe.append(cmd);
+                }
+                _ => return Err(self.unexpected(&token, context))
+            }
+        }
+    }
+
+    fn check_pipeline(&self, pipe: &PipeNode, context: &str) -> Result<(), ParseError>{
+        // 不允许空的 pipeline
+        if pipe.cmds.is_empty(){
+            return Err(self.errorf(format!("missing value for {}", context)));
+        }
+        // 只有第一个命令可以以不能执行的操作数开头
+        for (i, c) in pipe.cmds.iter().enumerate().skip(1){
+            match c.args[0]{
+                NodeType::EnumBool(_) | NodeType::EnumDot(_) | NodeType::EnumNil(_) |
+                NodeType::EnumNumber(_) | NodeType::EnumString(_) => {
+                    // A|B|C 里第二段是 B
+                    return Err(self.errorf(format!("non executable command in pipeline stage {}", i + 1)));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self) -> Result<CommandNode, ParseError>{
+        let mut cmd = CommandNode::new(self.peek_non_space().pos);
+        loop{
+            self.peek_non_space(); // 跳过前面的空白
+            if let Some(operand) = self.operand()?{
+                cmd.append(operand);
+            }
+            let token = self.next();
+            match token.typ{
+                ItemType::ItemSpace => continue,
+                ItemType::ItemRightDelim | ItemType::ItemRightParen => self.backup(),
+                ItemType::ItemPipe => {}
+                _ => return Err(self.unexpected(&token, "operand"))
+            }
+            break;
+        }
+        if cmd.args.is_empty(){
+            return Err(self.errorf(String::from("empty command")));
+        }
+        Ok(cmd)
+    }
+
+    // term 后面可能跟着一串字段: (.X).Y.Z
+    fn operand(&mut self) -> Result<Option<NodeType>, ParseError>{
+        let node = match self.term()?{
+            Some(node) => node,
+            None => return Ok(None)
+        };
+        if self.peek().typ != ItemType::ItemField{
+            return Ok(Some(node));
+        }
+        let mut chain = ChainNode::new(self.peek().pos, node);
+        while self.peek().typ == ItemType::ItemField{
+            let field = self.next();
+            chain.add(&field.val);
+        }
+        // 和 Go 一样，字段和变量后面的字段直接接到原来的节点上，其余的才保留 ChainNode。
+        // 明显不对的字面量在这里报错，更复杂的情况要等到执行时才能发现
+        let node = match *chain.node{
+            NodeType::EnumField(_) => NodeType::EnumField(FieldNode::new(chain.pos, &chain.string())),
+            NodeType::EnumVariable(_) => NodeType::EnumVariable(VariableNode::new(chain.pos, &chain.string())),
+            NodeType::EnumBool(_) | NodeType::EnumString(_) | NodeType::EnumNumber(_) |
+            NodeType::EnumNil(_) | NodeType::EnumDot(_) => {
+                return Err(self.errorf(format!("unexpected . after term {}", quote(&chain.node.string()))));
+            }
+            _ => NodeType::EnumChain(chain)
+        };
+        Ok(Some(node))
+    }
+
+    // 单个的值，不是 term 时退回 token 并返回 None
+    fn term(&mut self) -> Result<Option<NodeType>, ParseError>{
+        let token = self.next_non_space();
+        let node = match token.typ{
+            ItemType::ItemIdentifier => {
+                if !self.skip_func_check && !self.has_function(&token.val){
+                    return Err(self.errorf(format!("function {} not defined", quote(&token.val))));
+                }
+                NodeType::EnumIdentifier(IdentifierNode::new(token.pos, &token.val))
+            }
+            ItemType::ItemDot => NodeType::EnumDot(DotNode::new(token.pos)),
+            ItemType::ItemNil => NodeType::EnumNil(NilNode::new(token.pos)),
+            ItemType::ItemVariable => self.use_var(&token)?,
+            ItemType::ItemField => NodeType::EnumField(FieldNode::new(token.pos, &token.val)),
+            ItemType::ItemBool => NodeType::EnumBool(BoolNode::new(token.pos, token.val == "true")),
+            ItemType::ItemCharConstant | ItemType::ItemComplex | ItemType::ItemNumber => {
+                match NumberNode::new(token.pos, &token.val, token.typ){
+                    Ok(number) => NodeType::EnumNumber(number),
+                    Err(err) => return Err(self.errorf(err))
+                }
+            }
+            ItemType::ItemLeftParen => NodeType::EnumPipe(self.pipeline("parenthesized pipeline", ItemType::ItemRightParen)?),
+            ItemType::ItemString | ItemType::ItemRawString => {
+                match strconv::unquote_bytes(&token.val){
+                    Ok(s) => NodeType::EnumString(StringNode::new(token.pos, &token.val, &s)),
+                    Err(err) => return Err(self.errorf(err.msg))
+                }
+            }
+            _ => {
+                self.backup();
+                return Ok(None);
+            }
+        };
+        Ok(Some(node))
+    }
 }
 
-pub type CellTree = Rc<RefCell<Box<Tree>>>;
+// 模板是否只有空白文本和注释，这样的模板可以被同名的模板替换
+pub fn is_empty_tree(n: &NodeType) -> bool{
+    match *n{
+        NodeType::EnumAction(_) | NodeType::EnumIf(_) | NodeType::EnumRange(_) |
+        NodeType::EnumTemplate(_) | NodeType::EnumWith(_) => false,
+        NodeType::EnumComment(_) => true,
+        NodeType::EnumList(ref list) => is_empty_list(list),
+        NodeType::EnumText(ref text) => text.text.trim().is_empty(),
+        _ => panic!("unknown node: {}", n.string())
+    }
+}
 
+fn is_empty_list(list: &ListNode) -> bool{
+    list.nodes.iter().all(is_empty_tree)
+}
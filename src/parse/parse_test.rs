@@ -0,0 +1,393 @@
+use super::parse::*;
+use super::node::{Node, NodeType};
+
+// 和 Go 的 parse_test.go 一样，只认识这两个函数
+const BUILTINS: &[&str] = &["printf", "contains"];
+
+struct ParseTest{
+    name: &'static str,
+    input: &'static str,
+    ok: bool,
+    result: &'static str, // 出错时不检查
+}
+
+fn parse_test(name: &'static str, input: &'static str, ok: bool, result: &'static str) -> ParseTest{
+    ParseTest{
+        name,
+        input,
+        ok,
+        result,
+    }
+}
+
+const NO_ERROR: bool = true;
+const HAS_ERROR: bool = false;
+
+fn get_parse_tests() -> Vec<ParseTest>{
+    vec![
+        parse_test("empty", "", NO_ERROR, ""),
+        parse_test("comment", "{{/*\n\n\n*/}}", NO_ERROR, ""),
+        parse_test("spaces", " \t\n", NO_ERROR, " \t\n"),
+        parse_test("text", "some text", NO_ERROR, "some text"),
+        parse_test("emptyAction", "{{}}", HAS_ERROR, "{{}}"),
+        parse_test("field", "{{.X}}", NO_ERROR, "{{.X}}"),
+        parse_test("simple command", "{{printf}}", NO_ERROR, "{{printf}}"),
+        parse_test("$ invocation", "{{$}}", NO_ERROR, "{{$}}"),
+        parse_test("variable with fields", "{{$.I}}", NO_ERROR, "{{$.I}}"),
+        parse_test("multi-word command", "{{printf `%d` 23}}", NO_ERROR, "{{printf `%d` 23}}"),
+        parse_test("pipeline", "{{.X|.Y}}", NO_ERROR, "{{.X | .Y}}"),
+        parse_test("pipeline with decl", "{{$x := .X|.Y}}", NO_ERROR, "{{$x := .X | .Y}}"),
+        parse_test("nested pipeline", "{{.X (.Y .Z) (.A | .B .C) (.E)}}", NO_ERROR, "{{.X (.Y .Z) (.A | .B .C) (.E)}}"),
+        parse_test("field applied to parentheses", "{{(.Y .Z).Field}}", NO_ERROR, "{{(.Y .Z).Field}}"),
+        parse_test("text between actions", "{{.X}}hello{{.Y}}", NO_ERROR, "{{.X}}hello{{.Y}}"),
+        parse_test("declaration", "{{$x := 3}}{{$x}}", NO_ERROR, "{{$x := 3}}{{$x}}"),
+        parse_test("assignment", "{{$x := 3}}{{$x = 4}}", NO_ERROR, "{{$x := 3}}{{$x = 4}}"),
+        parse_test("variable as argument", "{{$x := 3}}{{printf $x 23}}", NO_ERROR, "{{$x := 3}}{{printf $x 23}}"),
+        parse_test("variable with trailing space", "{{$x := 3}}{{$x }}", NO_ERROR, "{{$x := 3}}{{$x}}"),
+        parse_test("variable fields", "{{$x := .}}{{$x.A.B}}", NO_ERROR, "{{$x := .}}{{$x.A.B}}"),
+        parse_test("field chain", "{{.X.Y.Z}}", NO_ERROR, "{{.X.Y.Z}}"),
+        parse_test("chained field", "{{(.X).Y}}", NO_ERROR, "{{(.X).Y}}"),
+        parse_test("string with invalid UTF-8", "{{printf \"\\xff\"}}", NO_ERROR, "{{printf \"\\xff\"}}"),
+        parse_test("constants", "{{printf 1 1.5 'a' 1i true nil \"s\"}}", NO_ERROR, "{{printf 1 1.5 'a' 1i true nil \"s\"}}"),
+        parse_test("dot", "{{.}}", NO_ERROR, "{{.}}"),
+        parse_test("trimming spaces", "x \r\n\t{{- 3 -}}\n\n\ty", NO_ERROR, "x{{3}}y"),
+        parse_test("trimming spaces before and after comment", "x \r\n\t{{- /* hi */ -}}\n\n\ty", NO_ERROR, "xy"),
+        parse_test("spaces in parenthesized pipeline", "{{printf \"%d\" ( 1 )}}", NO_ERROR, "{{printf \"%d\" (1)}}"),
+        parse_test("multiline action", "{{printf\n\"%d\"\n.X}}", NO_ERROR, "{{printf \"%d\" .X}}"),
+        parse_test("simple if", "{{if .X}}hello{{end}}", NO_ERROR, "{{if .X}}hello{{end}}"),
+        parse_test("if with else", "{{if .X}}true{{else}}false{{end}}", NO_ERROR, "{{if .X}}true{{else}}false{{end}}"),
+        parse_test("if with else if", "{{if .X}}true{{else if .Y}}false{{end}}", NO_ERROR,
+            "{{if .X}}true{{else}}{{if .Y}}false{{end}}{{end}}"),
+        parse_test("if else chain", "+{{if .X}}X{{else if .Y}}Y{{else if .Z}}Z{{end}}+", NO_ERROR,
+            "+{{if .X}}X{{else}}{{if .Y}}Y{{else}}{{if .Z}}Z{{end}}{{end}}{{end}}+"),
+        parse_test("simple range", "{{range .X}}hello{{end}}", NO_ERROR, "{{range .X}}hello{{end}}"),
+        parse_test("chained field range", "{{range .X.Y.Z}}hello{{end}}", NO_ERROR, "{{range .X.Y.Z}}hello{{end}}"),
+        parse_test("nested range", "{{range .X}}hello{{range .Y}}goodbye{{end}}{{end}}", NO_ERROR,
+            "{{range .X}}hello{{range .Y}}goodbye{{end}}{{end}}"),
+        parse_test("range with else", "{{range .X}}true{{else}}false{{end}}", NO_ERROR, "{{range .X}}true{{else}}false{{end}}"),
+        parse_test("range over pipeline", "{{range .X|.M}}true{{else}}false{{end}}", NO_ERROR,
+            "{{range .X | .M}}true{{else}}false{{end}}"),
+        parse_test("range []int", "{{range .SI}}{{.}}{{end}}", NO_ERROR, "{{range .SI}}{{.}}{{end}}"),
+        parse_test("range 1 var", "{{range $x := .SI}}{{.}}{{end}}", NO_ERROR, "{{range $x := .SI}}{{.}}{{end}}"),
+        parse_test("range 2 vars", "{{range $x, $y := .SI}}{{.}}{{end}}", NO_ERROR, "{{range $x, $y := .SI}}{{.}}{{end}}"),
+        parse_test("range with break", "{{range .SI}}{{.}}{{break}}{{end}}", NO_ERROR, "{{range .SI}}{{.}}{{break}}{{end}}"),
+        parse_test("range with continue", "{{range .SI}}{{.}}{{continue}}{{end}}", NO_ERROR, "{{range .SI}}{{.}}{{continue}}{{end}}"),
+        parse_test("break in nested if", "{{range .SI}}{{if .}}{{break}}{{end}}{{end}}", NO_ERROR,
+            "{{range .SI}}{{if .}}{{break}}{{end}}{{end}}"),
+        parse_test("with", "{{with .X}}hello{{end}}", NO_ERROR, "{{with .X}}hello{{end}}"),
+        parse_test("with with else", "{{with .X}}hello{{else}}goodbye{{end}}", NO_ERROR, "{{with .X}}hello{{else}}goodbye{{end}}"),
+        parse_test("with with else with", "{{with .X}}hello{{else with .Y}}goodbye{{end}}", NO_ERROR,
+            "{{with .X}}hello{{else}}{{with .Y}}goodbye{{end}}{{end}}"),
+        parse_test("with else chain", "{{with .X}}X{{else with .Y}}Y{{else with .Z}}Z{{end}}", NO_ERROR,
+            "{{with .X}}X{{else}}{{with .Y}}Y{{else}}{{with .Z}}Z{{end}}{{end}}{{end}}"),
+        parse_test("template", "{{template `x`}}", NO_ERROR, "{{template \"x\"}}"),
+        parse_test("template with arg", "{{template `x` .Y}}", NO_ERROR, "{{template \"x\" .Y}}"),
+        parse_test("block definition", "{{block \"foo\" .}}hello{{end}}", NO_ERROR, "{{template \"foo\" .}}"),
+        parse_test("definition is not in root", "a{{define `x`}}hello{{end}}b", NO_ERROR, "ab"),
+        parse_test("declared variable in range", "{{range $x := .}}{{$x}}{{end}}", NO_ERROR, "{{range $x := .}}{{$x}}{{end}}"),
+        parse_test("both range variables", "{{range $i, $e := .}}{{$i}}{{$e}}{{end}}", NO_ERROR,
+            "{{range $i, $e := .}}{{$i}}{{$e}}{{end}}"),
+        parse_test("variable in else", "{{if $x := 1}}{{else}}{{$x}}{{end}}", NO_ERROR, "{{if $x := 1}}{{else}}{{$x}}{{end}}"),
+        parse_test("variable in nested action", "{{$x := 1}}{{if .}}{{with .}}{{$x}}{{end}}{{end}}", NO_ERROR,
+            "{{$x := 1}}{{if .}}{{with .}}{{$x}}{{end}}{{end}}"),
+        parse_test("variable in parenthesized pipeline", "{{$x := 1}}{{printf (printf $x)}}", NO_ERROR,
+            "{{$x := 1}}{{printf (printf $x)}}"),
+        parse_test("with declaration", "{{with $x := 3}}{{$x 23}}{{end}}", NO_ERROR, "{{with $x := 3}}{{$x 23}}{{end}}"),
+        // 出错的情况
+        parse_test("unclosed action", "hello{{.X", HAS_ERROR, ""),
+        parse_test("undefined function", "hello{{undefined}}", HAS_ERROR, ""),
+        parse_test("adjacent args", "{{printf 3`x`}}", HAS_ERROR, ""),
+        parse_test("dot applied to parentheses", "{{printf (printf .).}}", HAS_ERROR, ""),
+        parse_test("bug0a", "{{$x := 0}}{{$x}}{{$x :=}}", HAS_ERROR, ""),
+        parse_test("bug0b", "{{$x += 1}}", HAS_ERROR, ""),
+        parse_test("bug0c", "{{$x ! 2}}", HAS_ERROR, ""),
+        parse_test("bug0d", "{{$x % 3}}", HAS_ERROR, ""),
+        parse_test("bug0e", "{{$x = 4}}{{$x :}}", HAS_ERROR, ""),
+        parse_test("bug1a", "{{$x:=.}}{{$x!2}}", HAS_ERROR, ""),
+        parse_test("bug1b", "{{$x:=.}}{{$x+2}}", HAS_ERROR, ""),
+        parse_test("dot after number", "{{1.E}}", HAS_ERROR, ""),
+        parse_test("dot after string", "{{\"hello\".guys}}", HAS_ERROR, ""),
+        parse_test("dot after nil", "{{nil.E}}", HAS_ERROR, ""),
+        parse_test("wrong pipeline dot", "{{12|.}}", HAS_ERROR, ""),
+        parse_test("wrong pipeline number", "{{.|12|printf}}", HAS_ERROR, ""),
+        parse_test("wrong pipeline string", "{{.|printf|\"error\"}}", HAS_ERROR, ""),
+        parse_test("wrong pipeline boolean", "{{.|true}}", HAS_ERROR, ""),
+        parse_test("wrong pipeline nil", "{{'c'|nil}}", HAS_ERROR, ""),
+        parse_test("empty pipeline", "{{printf \"%d\" ( ) }}", HAS_ERROR, ""),
+        parse_test("template without name", "{{template .X}}", HAS_ERROR, ""),
+        parse_test("block without end", "{{block `x` .}}a", HAS_ERROR, ""),
+        parse_test("nested definition", "{{if .X}}{{define `a`}}{{end}}{{end}}", HAS_ERROR, ""),
+        parse_test("undefined variable", "{{$x}}", HAS_ERROR, ""),
+        parse_test("variable undefined after end", "{{with $x := 4}}{{end}}{{$x}}", HAS_ERROR, ""),
+        parse_test("variable undefined in template", "{{template $v}}", HAS_ERROR, ""),
+        parse_test("declare with field", "{{with $x.Y := 4}}{{end}}", HAS_ERROR, ""),
+        parse_test("unmatched end", "{{end}}", HAS_ERROR, ""),
+        parse_test("unmatched else", "{{else}}", HAS_ERROR, ""),
+        parse_test("missing end", "hello{{range .x}}", HAS_ERROR, ""),
+        parse_test("missing end after else", "hello{{range .x}}{{else}}", HAS_ERROR, ""),
+        parse_test("else if in range", "{{range .X}}a{{else if .Y}}b{{end}}", HAS_ERROR, ""),
+        parse_test("break outside range", "{{range .}}{{end}} {{break}}", HAS_ERROR, ""),
+        parse_test("continue outside range", "{{range .}}{{end}} {{continue}}", HAS_ERROR, ""),
+        parse_test("break in range else", "{{range .}}{{else}}{{break}}{{end}}", HAS_ERROR, ""),
+        parse_test("continue in range else", "{{range .}}{{else}}{{continue}}{{end}}", HAS_ERROR, ""),
+    ]
+}
+
+#[test]
+fn test_parse(){
+    for test in get_parse_tests(){
+        let mut tree = Tree::new(test.name, BUILTINS);
+        match tree.parse(test.input, "", "", &mut TreeSet::new()){
+            Ok(()) => {
+                if !test.ok{
+                    panic!("{}: expected error; got none", test.name);
+                }
+                assert_eq!(tree.root.string(), test.result, "{}", test.name);
+            }
+            Err(err) => {
+                if test.ok{
+                    panic!("{}: unexpected error: {}", test.name, err);
+                }
+                assert!(tree.root.nodes.is_empty(), "{}: root is not empty after error", test.name);
+            }
+        }
+    }
+}
+
+// 解析结果复制以后输出不变
+#[test]
+fn test_parse_copy(){
+    for test in get_parse_tests().into_iter().filter(|t| t.ok){
+        let mut tree = Tree::new(test.name, BUILTINS);
+        tree.parse(test.input, "", "", &mut TreeSet::new()).unwrap();
+        assert_eq!(tree.copy().root.string(), test.result, "{}", test.name);
+    }
+}
+
+// 字符串常量和 Go 一样保留转义得到的字节，不要求是合法的 UTF-8
+#[test]
+fn test_string_bytes(){
+    let mut tree = Tree::new("bytes", BUILTINS);
+    tree.parse("{{printf \"\\xffé\\u00e9\"}}", "", "", &mut TreeSet::new()).unwrap();
+    let arg = match tree.root.nodes[0]{
+        NodeType::EnumAction(ref action) => action.pipe.cmds[0].args[1].clone(),
+        _ => panic!("expected an action"),
+    };
+    match arg{
+        NodeType::EnumString(ref s) => assert_eq!(s.text, b"\xff\xc3\xa9\xc3\xa9".to_vec()),
+        _ => panic!("expected a string"),
+    }
+}
+
+#[test]
+fn test_skip_func_check(){
+    let mut tree = Tree::new("skip func check", &[]);
+    tree.skip_func_check = true;
+    tree.parse("{{fn 1 2}}", "", "", &mut TreeSet::new()).unwrap();
+    assert_eq!(tree.root.string(), "{{fn 1 2}}");
+}
+
+#[test]
+fn test_parse_comments(){
+    let mut tree = Tree::new("comments", BUILTINS);
+    tree.parse_comments = true;
+    tree.parse("a{{/* hi */}}b", "", "", &mut TreeSet::new()).unwrap();
+    assert_eq!(tree.root.nodes.len(), 3);
+    assert_eq!(tree.root.string(), "a{{/* hi */}}b");
+}
+
+#[test]
+fn test_parse_delims(){
+    let mut tree = Tree::new("delims", BUILTINS);
+    tree.parse("[[.X]]{{.Y}}", "[[", "]]", &mut TreeSet::new()).unwrap();
+    assert_eq!(tree.root.string(), "{{.X}}{{.Y}}");
+}
+
+// (输入, 出错信息里应该包含的内容)
+fn get_error_tests() -> Vec<(&'static str, &'static str)>{
+    vec![
+        ("line1\n{{", "template: test:2: unclosed action"),
+        ("line1\n{{\"x\"\n\"y\"\n", "template: test:4: unclosed action started at test:2"),
+        ("{{\n\n\n\n\n", "template: test:6: unclosed action started at test:1"),
+        ("line1\n{{\nx\n}}", "template: test:3: function \"x\" not defined"),
+        ("{{foo}}", "function \"foo\" not defined"),
+        ("{{/*}}", "template: test:1: unclosed comment"),
+        ("{{/*\nhello\n}}", "template: test:1: unclosed comment"),
+        ("{{.X (1 2 3}}", "template: test:1: unclosed left paren"),
+        ("{{.X 1 2 3 ) }}", "template: test:1: unexpected \")\" in command"),
+        ("{{(.X 1 2 3", "template: test:1: unclosed action"),
+        ("{{`x`3}}", "template: test:1: unexpected \"3\" in operand"),
+        ("{{a#}}", "template: test:1: bad character U+0023 '#'"),
+        ("{{'a}}", "template: test:1: unterminated character constant"),
+        ("{{\"a}}", "template: test:1: unterminated quoted string"),
+        ("{{`a}}", "template: test:1: unterminated raw quoted string"),
+        ("{{0xi}}", "template: test:1: illegal number syntax: \"0xi\""),
+        ("{{$a,$b,$c := 23}}", "template: test:1: too many declarations in command"),
+        ("{{printf (printf .).}}", "unexpected <.> in operand"),
+        ("{{printf 3`x`}}", "unexpected \"`x`\" in operand"),
+        ("{{printf `x`.}}", "unexpected <.> in operand"),
+        ("{{printf `x`.Y}}", "unexpected . after term \"`x`\""),
+        ("{{.X|1}}", "non executable command in pipeline stage 2"),
+        ("{{printf \"%d\" ( ) }}", "missing value for parenthesized pipeline"),
+        ("{{1i.X}}", "unexpected . after term \"1i\""),
+        ("{{end}}", "template: test:1: unexpected {{end}}"),
+        ("a{{if .X}}b{{end}}{{else}}", "template: test:1: unexpected {{else}}"),
+        ("hello{{range .x}}\n", "template: test:2: unexpected EOF"),
+        ("{{if .X}}a{{else}}b{{else}}c{{end}}", "template: test:1: expected end; found {{else}}"),
+        ("{{if}}{{end}}", "template: test:1: missing value for if"),
+        ("{{range $u, $v, $w := 3}}{{end}}", "template: test:1: too many declarations in range"),
+        ("{{range $x, 3}}{{end}}", "template: test:1: range can only initialize variables"),
+        ("{{with $x, $y := 3}}{{end}}", "template: test:1: too many declarations in with"),
+        ("{{range .}}{{end}} {{break}}", "template: test:1: {{break}} outside {{range}}"),
+        ("{{range .}}{{else}}{{continue}}{{end}}", "template: test:1: {{continue}} outside {{range}}"),
+        ("{{range .}}{{break 1}}{{end}}", "template: test:1: unexpected \"1\" in {{break}}"),
+        ("{{range .}}{{end 1}}", "template: test:1: unexpected \"1\" in end"),
+        ("{{template .X}}", "template: test:1: unexpected \".X\" in template clause"),
+        ("{{block 3 .}}{{end}}", "template: test:1: unexpected \"3\" in block clause"),
+        ("{{define a}}{{end}}", "template: test:1: unexpected \"a\" in define clause"),
+        ("{{define `a` .}}{{end}}", "template: test:1: unexpected <.> in define clause"),
+        ("{{define `a`}}a{{else}}b{{end}}", "template: test:1: unexpected {{else}} in define clause"),
+        ("{{block `a` .}}a{{else}}b{{end}}", "template: test:1: unexpected {{else}} in block clause"),
+        ("{{define `a`}}a", "template: test:1: unexpected EOF"),
+        ("{{if .X}}{{define `a`}}{{end}}{{end}}", "template: test:1: unexpected <define> in command"),
+        ("{{define `a`}}a{{end}}\n{{define `a`}}b{{end}}", "template: test:2: template: multiple definition of template \"a\""),
+        ("{{block `test` .}}a{{end}}", "template: test:1: template: multiple definition of template \"test\""),
+        ("{{$x}}", "template: test:1: undefined variable \"$x\""),
+        ("{{$x.Y}}", "template: test:1: undefined variable \"$x\""),
+        ("{{with $x := 4}}{{end}}{{$x}}", "template: test:1: undefined variable \"$x\""),
+        ("{{range $i, $e := .}}{{end}}{{$i}}", "template: test:1: undefined variable \"$i\""),
+        ("{{if .}}{{$x := 1}}{{else}}{{end}}{{$x}}", "template: test:1: undefined variable \"$x\""),
+        ("{{$x := 1}}{{define `a`}}{{$x}}{{end}}", "template: test:1: undefined variable \"$x\""),
+        ("{{$x := 1}}{{block `a` .}}{{$x}}{{end}}", "template: test:1: undefined variable \"$x\""),
+        ("{{if .X}}{{else .Y}}{{end}}", "template: test:1: unexpected \".Y\" in else"),
+        ("{{define \"\\xff\"}}{{end}}", "template: test:1: invalid UTF-8 encoding"),
+        ("{{template \"\\xff\"}}", "template: test:1: invalid UTF-8 encoding"),
+    ]
+}
+
+#[test]
+fn test_errors(){
+    for (input, expected) in get_error_tests(){
+        let mut tree = Tree::new("test", BUILTINS);
+        match tree.parse(input, "", "", &mut TreeSet::new()){
+            Ok(()) => panic!("{:?}: expected error", input),
+            Err(err) => {
+                let msg = err.to_string();
+                if !msg.contains(expected){
+                    panic!("{:?}: error {:?} does not contain {:?}", input, msg, expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_error_position(){
+    let mut tree = Tree::new("pos", BUILTINS);
+    let err = tree.parse("a\nbc{{.X | 1}}", "", "", &mut TreeSet::new()).unwrap_err();
+    assert_eq!(err.msg, "non executable command in pipeline stage 2");
+    // 和 Go 一样报告的是最后读到的 token，也就是右分隔符
+    assert_eq!((err.line, err.col), (2, 11));
+    assert_eq!(err.pos, 12);
+}
+
+#[test]
+fn test_control_error_position(){
+    let mut tree = Tree::new("pos", BUILTINS);
+    let err = tree.parse("{{range .}}\n{{end}}\n  {{break}}", "", "", &mut TreeSet::new()).unwrap_err();
+    assert_eq!(err.to_string(), "template: pos:3: {{break}} outside {{range}}");
+    assert_eq!((err.line, err.col), (3, 10));
+
+    let err = tree.parse("{{if .X}}\n\n{{end}}{{end}}", "", "", &mut TreeSet::new()).unwrap_err();
+    assert_eq!(err.to_string(), "template: pos:3: unexpected {{end}}");
+    assert_eq!((err.line, err.col), (3, 13));
+}
+
+#[test]
+fn test_undefined_variable_position(){
+    let mut tree = Tree::new("pos", BUILTINS);
+    let err = tree.parse("{{$x := 1}}\n{{printf $y 1}}", "", "", &mut TreeSet::new()).unwrap_err();
+    assert_eq!(err.to_string(), "template: pos:2: undefined variable \"$y\"");
+    assert_eq!((err.pos, err.line, err.col), (21, 2, 10));
+
+    // 后面还有一个 token 被预读了，位置也要是变量自己的
+    let err = tree.parse("{{$x := 1}}{{$y\n}}", "", "", &mut TreeSet::new()).unwrap_err();
+    assert_eq!((err.pos, err.line, err.col), (13, 1, 14));
+}
+
+#[test]
+fn test_error_context(){
+    let mut tree = Tree::new("ctx", BUILTINS);
+    tree.parse("hello\nworld {{.X.Y}}", "", "", &mut TreeSet::new()).unwrap();
+    let (location, context) = tree.error_context(&tree.root.nodes[1]);
+    assert_eq!(location, "ctx:2:8");
+    assert_eq!(context, "{{.X.Y}}");
+}
+
+// (输入, 所有模板的名字和内容)
+fn get_multi_parse_tests() -> Vec<(&'static str, Vec<(&'static str, &'static str)>)>{
+    vec![
+        ("", vec![("", "")]),
+        ("{{define \"foo\"}}hello{{end}}", vec![("", ""), ("foo", "hello")]),
+        ("{{define \"foo\"}}hello{{end}}{{define \"bar\"}}goodbye{{end}}",
+            vec![("", ""), ("foo", "hello"), ("bar", "goodbye")]),
+        ("a{{block \"inner\" .}}bar{{.}}baz{{end}}b",
+            vec![("", "a{{template \"inner\" .}}b"), ("inner", "bar{{.}}baz")]),
+        ("{{define `x`}}{{block `y` .X}}y{{end}}{{end}}",
+            vec![("", ""), ("x", "{{template \"y\" .X}}"), ("y", "y")]),
+        // 空的模板可以被替换，非空的模板不会被空的模板覆盖
+        ("{{define `x`}} {{end}}{{define `x`}}x{{end}}", vec![("", ""), ("x", "x")]),
+        ("{{define `x`}}x{{end}}{{define `x`}}{{/* c */}}{{end}}", vec![("", ""), ("x", "x")]),
+    ]
+}
+
+#[test]
+fn test_multi_parse(){
+    for (input, expected) in get_multi_parse_tests(){
+        let tree_set = parse("", input, "", "", BUILTINS).unwrap();
+        assert_eq!(tree_set.len(), expected.len(), "{:?}", input);
+        for (name, result) in expected{
+            let tree = match tree_set.get(name){
+                Some(tree) => tree,
+                None => panic!("{:?}: missing template {:?}", input, name),
+            };
+            assert_eq!(tree.name, name);
+            assert_eq!(tree.parse_name, "");
+            assert_eq!(tree.root.string(), result, "{:?}: template {:?}", input, name);
+        }
+    }
+}
+
+#[test]
+fn test_redefinition(){
+    // 和 Go 的 text/template 一样，多次解析共用一个 tree_set
+    let mut tree_set = TreeSet::new();
+    Tree::new("a", BUILTINS).parse("{{define `x`}}{{end}}", "", "", &mut tree_set).unwrap();
+    Tree::new("b", BUILTINS).parse("{{define `x`}}x{{end}}", "", "", &mut tree_set).unwrap();
+    assert_eq!(tree_set["x"].root.string(), "x");
+    Tree::new("c", BUILTINS).parse("{{define `x`}}\n{{end}}", "", "", &mut tree_set).unwrap();
+    assert_eq!(tree_set["x"].root.string(), "x");
+    let err = Tree::new("d", BUILTINS).parse("{{define `x`}}y{{end}}", "", "", &mut tree_set).unwrap_err();
+    assert_eq!(err.to_string(), "template: d:1: template: multiple definition of template \"x\"");
+    assert_eq!(tree_set.len(), 4);
+}
+
+#[test]
+fn test_is_empty(){
+    let tests = vec![
+        ("", true),
+        ("hello", false),
+        (" \t\n \t\n", true),
+        ("{{/* comment */}}", true),
+        ("{{define `x`}}something{{end}}", true),
+        ("{{define `x`}}something{{end}}\n\n{{define `y`}}something{{end}}\n\n", true),
+        ("{{define `x`}}something{{end}}\nx\n{{define `y`}}something{{end}}\ny\n", false),
+        ("{{define `x`}}something{{end}}{{if 3}}foo{{end}}", false),
+    ];
+    for (input, empty) in tests{
+        let mut tree = Tree::new("root", BUILTINS);
+        tree.parse(input, "", "", &mut TreeSet::new()).unwrap();
+        let root = NodeType::EnumList(tree.root.copy_list());
+        assert_eq!(is_empty_tree(&root), empty, "{:?}", input);
+    }
+}
@@ -0,0 +1,619 @@
+use super::lex::{format_rune, quote, ItemType};
+use super::node::Pos;
+use std::fmt;
+
+// 把字符串、字符和数字 token 的源码转换成实际的值，
+// 行为和 Go 的 strconv.Unquote、strconv.UnquoteChar 以及 parse.Tree.newNumber 一致
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnquoteError{
+    // 出错的位置，是相对于传入的字符串开头的字节偏移，
+    // 加上 item.pos 就是在模板里的位置
+    pub pos: Pos,
+    pub msg: String,
+}
+
+impl fmt::Display for UnquoteError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}", self.msg)
+    }
+}
+
+fn error<T>(pos: Pos, msg: String) -> Result<T, UnquoteError>{
+    Err(UnquoteError{pos, msg})
+}
+
+// 解码 s 开头的一个字符或者转义序列，quote 是所在字面量的引号。
+// 返回解码出的字符、是否需要按 UTF-8 编码（\x 和八进制转义表示的是单个字节），以及剩下的部分
+pub fn unquote_char(s: &str, quote: char) -> Result<(char, bool, &str), UnquoteError>{
+    let c = match s.chars().next(){
+        None => return error(0, String::from("escape sequence not terminated")),
+        Some(c) => c,
+    };
+    if c == quote && (quote == '\'' || quote == '"'){
+        return error(0, format!("unescaped {} in literal", c));
+    }
+    if c != '\\'{
+        return Ok((c, c.len_utf8() > 1, &s[c.len_utf8()..]));
+    }
+    // 反斜杠开头的转义序列
+    let b = s.as_bytes();
+    if b.len() <= 1{
+        return error(1, String::from("escape sequence not terminated"));
+    }
+    let value = match b[1]{
+        b'a' => '\u{0007}',
+        b'b' => '\u{0008}',
+        b'f' => '\u{000C}',
+        b'n' => '\n',
+        b'r' => '\r',
+        b't' => '\t',
+        b'v' => '\u{000B}',
+        b'\\' => '\\',
+        c @ b'x' | c @ b'u' | c @ b'U' => {
+            let n = match c{
+                b'x' => 2,
+                b'u' => 4,
+                _ => 8,
+            };
+            let mut v: u32 = 0;
+            for j in 2..2 + n{
+                // 前面的都是 ASCII 的十六进制数字，所以 j 一定落在字符边界上
+                let d = match s[j..].chars().next(){
+                    None => return error(j, String::from("escape sequence not terminated")),
+                    Some(d) => d,
+                };
+                match d.to_digit(16){
+                    None => return error(j, format!("illegal character {} in escape sequence", format_rune(d))),
+                    Some(x) => v = v << 4 | x,
+                }
+            }
+            let rest = &s[2 + n..];
+            if c == b'x'{
+                // 单个字节，不一定是合法的 UTF-8
+                return Ok((v as u8 as char, false, rest));
+            }
+            match ::std::char::from_u32(v){
+                None => return error(0, String::from("escape sequence is invalid Unicode code point")),
+                Some(r) => return Ok((r, true, rest)),
+            }
+        },
+        c @ b'0' ..= b'7' => {
+            let mut v = (c - b'0') as u32;
+            for j in 2..4{
+                let d = match s[j..].chars().next(){
+                    None => return error(j, String::from("escape sequence not terminated")),
+                    Some(d) => d,
+                };
+                match d.to_digit(8){
+                    None => return error(j, format!("illegal character {} in escape sequence", format_rune(d))),
+                    Some(x) => v = v << 3 | x,
+                }
+            }
+            if v > 255{
+                return error(0, String::from("octal escape value > 255"));
+            }
+            return Ok((v as u8 as char, false, &s[4..]));
+        },
+        // 只能转义所在字面量自己的引号，"\'" 和 '\"' 都是非法的
+        c @ b'\'' | c @ b'"' if c as char == quote => c as char,
+        _ => {
+            let c = s[1..].chars().next().unwrap();
+            return error(0, format!("unknown escape sequence \\{}", c));
+        }
+    };
+    Ok((value, false, &s[2..]))
+}
+
+// 和 Go 的 strconv.Unquote 一样，\x 和八进制转义得到的字节原样保留，结果不一定是合法的 UTF-8
+pub fn unquote_bytes(s: &str) -> Result<Vec<u8>, UnquoteError>{
+    let mut escapes = vec![];
+    unquote_with(s, &mut escapes)
+}
+
+// 解码成 String，如果 \x 或八进制转义拼出来的字节不是合法的 UTF-8 就报错。
+// 这一点和 Go 不同，需要和 Go 完全一致时用 unquote_bytes
+pub fn unquote(s: &str) -> Result<String, UnquoteError>{
+    let mut escapes = vec![];
+    let buf = unquote_with(s, &mut escapes)?;
+    match String::from_utf8(buf){
+        Ok(r) => Ok(r),
+        Err(e) => {
+            // 字面上的字符一定是完整的 UTF-8，所以出错的字节一定来自某个单字节转义
+            let at = e.utf8_error().valid_up_to();
+            let pos = match escapes.iter().find(|x| x.0 == at){
+                Some(x) => x.1,
+                None => 0,
+            };
+            error(pos, String::from("invalid UTF-8 encoding"))
+        }
+    }
+}
+
+// escapes 记录每个非 ASCII 的单字节转义: (在结果里的偏移, 在 s 里的偏移)
+fn unquote_with(s: &str, escapes: &mut Vec<(usize, Pos)>) -> Result<Vec<u8>, UnquoteError>{
+    let quote = match s.chars().next(){
+        Some(c) if c == '"' || c == '`' || c == '\'' => c,
+        _ => return error(0, String::from("invalid syntax")),
+    };
+    if quote == '`'{
+        if s.len() < 2 || !s.ends_with('`'){
+            return error(s.len(), String::from("raw string literal not terminated"));
+        }
+        let body = &s[1..s.len() - 1];
+        if let Some(i) = body.find('`'){
+            return error(1 + i, String::from("unexpected ` in raw string literal"));
+        }
+        // 和 Go 一样去掉原始字符串里的 \r
+        return Ok(body.bytes().filter(|b| *b != b'\r').collect());
+    }
+    let not_terminated = if quote == '"'{
+        "string literal not terminated"
+    }else{
+        "rune literal not terminated"
+    };
+    let mut buf = Vec::with_capacity(s.len());
+    let mut pos = 1;
+    loop{
+        let rest = &s[pos..];
+        match rest.chars().next(){
+            None => return error(pos, String::from(not_terminated)),
+            Some(c) if c == quote => break,
+            Some('\n') => return error(pos, String::from(not_terminated)),
+            _ => (),
+        }
+        if quote == '\'' && pos > 1{
+            return error(pos, String::from("more than one character in rune literal"));
+        }
+        let (r, multibyte, tail) = match unquote_char(rest, quote){
+            Ok(x) => x,
+            Err(e) => return error(pos + e.pos, e.msg),
+        };
+        if (r as u32) < 0x80 || !multibyte{
+            if (r as u32) >= 0x80{
+                escapes.push((buf.len(), pos));
+            }
+            buf.push(r as u32 as u8);
+        }else{
+            let mut tmp = [0; 4];
+            buf.extend_from_slice(r.encode_utf8(&mut tmp).as_bytes());
+        }
+        pos = s.len() - tail.len();
+    }
+    if quote == '\'' && pos == 1{
+        return error(pos, String::from("empty rune literal or unescaped ' in rune literal"));
+    }
+    // 结束的引号后面不能再有别的内容
+    if pos + 1 != s.len(){
+        return error(pos + 1, String::from("invalid syntax"));
+    }
+    Ok(buf)
+}
+
+// 数字字面量的值，和 Go 的 NumberNode 一样同时记录它能表示成哪几种类型。
+// 比如 1e3 既是整数也是浮点数，'a' 是 97
+#[derive(Debug, Clone, PartialEq)]
+pub struct Number{
+    pub is_int: bool,
+    pub is_uint: bool,
+    pub is_float: bool,
+    pub is_complex: bool,
+    pub int64: i64,
+    pub uint64: u64,
+    pub float64: f64,
+    pub complex128: (f64, f64), // (实部, 虚部)
+}
+
+impl Number{
+    fn new() -> Number{
+        Number{
+            is_int: false,
+            is_uint: false,
+            is_float: false,
+            is_complex: false,
+            int64: 0,
+            uint64: 0,
+            float64: 0.0,
+            complex128: (0.0, 0.0),
+        }
+    }
+
+    // 虚部为 0 的复数同时也是浮点数，可能还是整数
+    fn simplify_complex(&mut self){
+        self.is_float = self.complex128.1 == 0.0;
+        if self.is_float{
+            self.float64 = self.complex128.0;
+            match float_to_int(self.float64){
+                Some(i) => {
+                    self.is_int = true;
+                    self.int64 = i;
+                },
+                None => self.is_int = false,
+            }
+            match float_to_uint(self.float64){
+                Some(u) => {
+                    self.is_uint = true;
+                    self.uint64 = u;
+                },
+                None => self.is_uint = false,
+            }
+        }
+    }
+}
+
+// 计算 ItemNumber、ItemComplex、ItemCharConstant 的值，规则和 Go 的 Tree.newNumber 一样
+pub fn parse_number(text: &str, typ: ItemType) -> Result<Number, String>{
+    let mut n = Number::new();
+    match typ{
+        ItemType::ItemCharConstant => {
+            if !text.starts_with('\''){
+                return Err(format!("malformed character constant: {}", text));
+            }
+            let (r, _, tail) = match unquote_char(&text[1..], '\''){
+                Ok(x) => x,
+                Err(e) => return Err(e.msg),
+            };
+            if tail != "'"{
+                return Err(format!("malformed character constant: {}", text));
+            }
+            n.int64 = r as i64;
+            n.is_int = true;
+            n.uint64 = r as u64;
+            n.is_uint = true;
+            n.float64 = r as u32 as f64; // 有点奇怪，但 Go 就是这么规定的
+            n.is_float = true;
+            return Ok(n);
+        },
+        ItemType::ItemComplex => {
+            n.complex128 = scan_complex(text)?;
+            n.is_complex = true;
+            n.simplify_complex();
+            return Ok(n);
+        },
+        _ => (),
+    }
+    // 纯虚数只能是复数，除非它是 0
+    if let Some(imag) = text.strip_suffix('i'){
+        if let Ok(f) = parse_float(imag){
+            n.is_complex = true;
+            n.complex128 = (0.0, f);
+            n.simplify_complex();
+            return Ok(n);
+        }
+    }
+    // 先按整数解析，这样 0x123 之类的才能得到正确的值
+    let u = parse_uint(text);
+    if let Ok(u) = u{
+        n.is_uint = true;
+        n.uint64 = u;
+    }
+    if let Ok(i) = parse_int(text){
+        n.is_int = true;
+        n.int64 = i;
+        if i == 0{
+            // -0 也是 uint
+            n.is_uint = true;
+            n.uint64 = 0;
+        }
+    }
+    if n.is_int{
+        n.is_float = true;
+        n.float64 = n.int64 as f64;
+    }else if n.is_uint{
+        n.is_float = true;
+        n.float64 = n.uint64 as f64;
+    }else if let Ok(f) = parse_float(text){
+        // 能按浮点数解析却看起来像整数，说明整数太大了
+        if !text.contains(['.', 'e', 'E', 'p', 'P']){
+            return Err(format!("integer overflow: {}", quote(text)));
+        }
+        n.is_float = true;
+        n.float64 = f;
+        if let Some(i) = float_to_int(f){
+            n.is_int = true;
+            n.int64 = i;
+        }
+        if let Some(u) = float_to_uint(f){
+            n.is_uint = true;
+            n.uint64 = u;
+        }
+    }
+    if !n.is_int && !n.is_uint && !n.is_float{
+        return Err(format!("illegal number syntax: {}", quote(text)));
+    }
+    Ok(n)
+}
+
+// f 是否正好是一个 int64，不能直接用 as，因为 as 超出范围时会取最大值
+fn float_to_int(f: f64) -> Option<i64>{
+    if (-9223372036854775808.0..9223372036854775808.0).contains(&f) && f.trunc() == f{
+        return Some(f as i64);
+    }
+    None
+}
+
+fn float_to_uint(f: f64) -> Option<u64>{
+    if (0.0..18446744073709551616.0).contains(&f) && f.trunc() == f{
+        return Some(f as u64);
+    }
+    None
+}
+
+const ERRSYNTAX: &str = "invalid syntax";
+const ERRRANGE: &str = "value out of range";
+
+// 和 Go 的 strconv.ParseUint(s, 0, 64) 一样，根据前缀判断进制，允许用 _ 分隔数字
+fn parse_uint(s: &str) -> Result<u64, &'static str>{
+    if s.is_empty(){
+        return Err(ERRSYNTAX);
+    }
+    let b = s.as_bytes();
+    let (base, digits) = if b[0] != b'0'{
+        (10, s)
+    }else if b.len() >= 3 && (b[1] == b'b' || b[1] == b'B'){
+        (2, &s[2..])
+    }else if b.len() >= 3 && (b[1] == b'o' || b[1] == b'O'){
+        (8, &s[2..])
+    }else if b.len() >= 3 && (b[1] == b'x' || b[1] == b'X'){
+        (16, &s[2..])
+    }else{
+        // 0 开头的是八进制
+        (8, &s[1..])
+    };
+    let mut n: u64 = 0;
+    let mut underscores = false;
+    for c in digits.chars(){
+        if c == '_'{
+            underscores = true;
+            continue;
+        }
+        let d = match c.to_digit(base){
+            None => return Err(ERRSYNTAX),
+            Some(d) => d as u64,
+        };
+        n = match n.checked_mul(base as u64).and_then(|n| n.checked_add(d)){
+            None => return Err(ERRRANGE),
+            Some(n) => n,
+        };
+    }
+    if underscores && !underscore_ok(s){
+        return Err(ERRSYNTAX);
+    }
+    Ok(n)
+}
+
+// 去掉开头的正负号，返回是否为负数
+fn split_sign(s: &str) -> (bool, &str){
+    if let Some(rest) = s.strip_prefix('-'){
+        return (true, rest);
+    }
+    if let Some(rest) = s.strip_prefix('+'){
+        return (false, rest);
+    }
+    (false, s)
+}
+
+// 和 Go 的 strconv.ParseInt(s, 0, 64) 一样
+fn parse_int(s: &str) -> Result<i64, &'static str>{
+    let (neg, rest) = split_sign(s);
+    let u = parse_uint(rest)?;
+    let cutoff = 1u64 << 63;
+    if !neg && u >= cutoff{
+        return Err(ERRRANGE);
+    }
+    if neg && u > cutoff{
+        return Err(ERRRANGE);
+    }
+    if neg{
+        return Ok((u as i64).wrapping_neg());
+    }
+    Ok(u as i64)
+}
+
+// 和 Go 的 strconv.ParseFloat(s, 64) 一样，支持十进制和带 p 指数的十六进制浮点数
+fn parse_float(s: &str) -> Result<f64, &'static str>{
+    if s.contains('_') && !underscore_ok(s){
+        return Err(ERRSYNTAX);
+    }
+    let (neg, rest) = split_sign(s);
+    let f = if let Some(hex) = rest.strip_prefix("0x").or_else(|| rest.strip_prefix("0X")){
+        parse_hex_float(hex)?
+    }else{
+        // 标准库的解析是正确舍入的，但不认识 _，也会接受 inf、nan 这样的写法
+        if rest.starts_with('+') || rest.starts_with('-') || !rest.chars().all(|c| DECIMALFLOAT.contains(c)){
+            return Err(ERRSYNTAX);
+        }
+        let digits: String = rest.chars().filter(|c| *c != '_').collect();
+        match digits.parse::<f64>(){
+            Err(_) => return Err(ERRSYNTAX),
+            Ok(f) => f,
+        }
+    };
+    if f.is_infinite(){
+        return Err(ERRRANGE);
+    }
+    if neg{
+        return Ok(-f);
+    }
+    Ok(f)
+}
+
+const DECIMALFLOAT: &str = "0123456789_.eE+-";
+
+// 解析 0x 后面的部分，如 1.8p-3。十六进制的浮点数必须有 p 指数
+fn parse_hex_float(s: &str) -> Result<f64, &'static str>{
+    let b = s.as_bytes();
+    let mut i = 0;
+    let mut mantissa: u64 = 0;
+    let mut exp: i64 = 0;
+    let mut saw_digits = false;
+    let mut saw_dot = false;
+    let mut sticky = false;
+    while i < b.len(){
+        let c = b[i];
+        if c == b'_'{
+            i += 1;
+            continue;
+        }
+        if c == b'.' && !saw_dot{
+            saw_dot = true;
+            i += 1;
+            continue;
+        }
+        let d = match (c as char).to_digit(16){
+            None => break,
+            Some(d) => d as u64,
+        };
+        saw_digits = true;
+        if mantissa >> 60 == 0{
+            mantissa = mantissa << 4 | d;
+            if saw_dot{
+                exp -= 4;
+            }
+        }else{
+            // 放不下的位只需要记住是不是 0，用于舍入
+            if d != 0{
+                sticky = true;
+            }
+            if !saw_dot{
+                exp += 4;
+            }
+        }
+        i += 1;
+    }
+    if !saw_digits || i >= b.len() || (b[i] != b'p' && b[i] != b'P'){
+        return Err(ERRSYNTAX);
+    }
+    i += 1;
+    let mut exp_neg = false;
+    if i < b.len() && (b[i] == b'+' || b[i] == b'-'){
+        exp_neg = b[i] == b'-';
+        i += 1;
+    }
+    let mut e: i64 = 0;
+    let mut saw_exp = false;
+    while i < b.len(){
+        if b[i] == b'_'{
+            i += 1;
+            continue;
+        }
+        if b[i] < b'0' || b'9' < b[i]{
+            return Err(ERRSYNTAX);
+        }
+        saw_exp = true;
+        // 指数很大的时候结果已经是 0 或者溢出了，不需要精确的值
+        if e < 100000{
+            e = e * 10 + (b[i] - b'0') as i64;
+        }
+        i += 1;
+    }
+    if !saw_exp{
+        return Err(ERRSYNTAX);
+    }
+    if exp_neg{
+        e = -e;
+    }
+    if sticky{
+        mantissa |= 1;
+    }
+    // u64 转 f64 是正确舍入的，之后乘以 2 的幂是精确的
+    let mut f = mantissa as f64;
+    let mut shift = exp + e;
+    while shift > 1000{
+        f *= 2f64.powi(1000);
+        shift -= 1000;
+    }
+    while shift < -1000{
+        f *= 2f64.powi(-1000);
+        shift += 1000;
+    }
+    Ok(f * 2f64.powi(shift as i32))
+}
+
+// 和 Go 的 strconv.underscoreOK 一样: _ 只能出现在两个数字之间，或者进制前缀和数字之间
+fn underscore_ok(s: &str) -> bool{
+    let mut b = s.as_bytes();
+    // saw: '^' 开头，'0' 数字或进制前缀，'_' 下划线，'!' 其它
+    let mut saw = b'^';
+    let mut i = 0;
+    if !b.is_empty() && (b[0] == b'-' || b[0] == b'+'){
+        b = &b[1..];
+    }
+    let mut hex = false;
+    if b.len() >= 2 && b[0] == b'0' && (b[1] | 0x20 == b'b' || b[1] | 0x20 == b'o' || b[1] | 0x20 == b'x'){
+        i = 2;
+        saw = b'0';
+        hex = b[1] | 0x20 == b'x';
+    }
+    while i < b.len(){
+        let c = b[i];
+        i += 1;
+        if c.is_ascii_digit() || (hex && b'a' <= c | 0x20 && c | 0x20 <= b'f'){
+            saw = b'0';
+            continue;
+        }
+        if c == b'_'{
+            if saw != b'0'{
+                return false;
+            }
+            saw = b'_';
+            continue;
+        }
+        if saw == b'_'{
+            return false;
+        }
+        saw = b'!';
+    }
+    saw != b'_'
+}
+
+// 和 Go 的 fmt.Sscan 读取 complex128 一样: 实部，必须有的正负号，虚部，最后是 i
+fn scan_complex(text: &str) -> Result<(f64, f64), String>{
+    let complex_error = String::from("syntax error scanning complex number");
+    let (real, rest) = float_token(text);
+    if !rest.starts_with('+') && !rest.starts_with('-'){
+        return Err(complex_error);
+    }
+    let (imag, rest) = float_token(&rest[1..]);
+    // 虚部要带上前面的符号
+    let imag = &text[real.len()..real.len() + 1 + imag.len()];
+    if !rest.starts_with('i'){
+        return Err(complex_error);
+    }
+    let convert = |s: &str| -> Result<f64, String>{
+        match parse_float(s){
+            Ok(f) => Ok(f),
+            Err(e) => Err(format!("strconv.ParseFloat: parsing {}: {}", quote(s), e)),
+        }
+    };
+    Ok((convert(real)?, convert(imag)?))
+}
+
+// 和 Go 的 fmt 包里的 floatToken 一样，只找出浮点数的边界
+fn float_token(s: &str) -> (&str, &str){
+    let b = s.as_bytes();
+    let mut i = 0;
+    let accept = |i: &mut usize, valid: &str| -> bool{
+        if *i < b.len() && valid.as_bytes().contains(&b[*i]){
+            *i += 1;
+            return true;
+        }
+        false
+    };
+    accept(&mut i, "+-");
+    let mut digits = "0123456789_";
+    let mut exp = "eEpP";
+    if accept(&mut i, "0") && accept(&mut i, "xX"){
+        digits = "0123456789aAbBcCdDeEfF_";
+        exp = "pP";
+    }
+    while accept(&mut i, digits){}
+    if accept(&mut i, "."){
+        while accept(&mut i, digits){}
+    }
+    if accept(&mut i, exp){
+        accept(&mut i, "+-");
+        while accept(&mut i, "0123456789_"){}
+    }
+    (&s[..i], &s[i..])
+}
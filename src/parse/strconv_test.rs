@@ -0,0 +1,265 @@
+use super::lex::ItemType;
+use super::strconv::*;
+
+// 和 Go 的 strconv 测试里的 unquotetests 基本一致
+fn get_unquote_tests() -> Vec<(&'static str, &'static str)>{
+    vec![
+        (r#""""#, ""),
+        (r#""a""#, "a"),
+        (r#""abc""#, "abc"),
+        (r#""☺""#, "☺"),
+        (r#""hello world""#, "hello world"),
+        (r#""\u1234""#, "\u{1234}"),
+        (r#""\U00010111""#, "\u{10111}"),
+        (r#""\U0001011111""#, "\u{10111}11"),
+        (r#""\a\b\f\n\r\t\v\\\"""#, "\u{0007}\u{0008}\u{000C}\n\r\t\u{000B}\\\""),
+        (r#""'""#, "'"),
+        (r#""a\tbé""#, "a\tbé"),
+        (r#""\xc3\xa9""#, "é"),
+        ("'a'", "a"),
+        ("'☹'", "☹"),
+        (r"'\a'", "\u{0007}"),
+        (r"'\x10'", "\u{0010}"),
+        (r"'\u1234'", "\u{1234}"),
+        (r"'\U00010111'", "\u{10111}"),
+        (r"'\''", "'"),
+        (r#"'"'"#, "\""),
+        ("``", ""),
+        ("`a`", "a"),
+        ("`abc`", "abc"),
+        ("`☺`", "☺"),
+        ("`hello world`", "hello world"),
+        (r"`\xFF`", r"\xFF"),
+        (r"`\377`", r"\377"),
+        ("`\\`", "\\"),
+        ("`\n`", "\n"),
+        ("`\t`", "\t"),
+        ("` `", " "),
+        ("`a\rb`", "ab"),
+    ]
+}
+
+// (输入, 出错位置, 错误信息)
+fn get_misquoted_tests() -> Vec<(&'static str, usize, &'static str)>{
+    vec![
+        ("", 0, "invalid syntax"),
+        ("\"", 1, "string literal not terminated"),
+        ("\"a", 2, "string literal not terminated"),
+        ("\"'", 2, "string literal not terminated"),
+        ("b\"", 0, "invalid syntax"),
+        ("\"\\\"", 3, "string literal not terminated"),
+        ("\"a\nb\"", 2, "string literal not terminated"),
+        (r#""\9""#, 1, r"unknown escape sequence \9"),
+        (r#""\19""#, 3, "illegal character U+0039 '9' in escape sequence"),
+        (r#""\129""#, 4, "illegal character U+0039 '9' in escape sequence"),
+        (r#""\400""#, 1, "octal escape value > 255"),
+        (r#""ab\x4g""#, 6, "illegal character U+0067 'g' in escape sequence"),
+        (r#""\x1""#, 4, "illegal character U+0022 '\"' in escape sequence"),
+        (r#""\u12""#, 5, "illegal character U+0022 '\"' in escape sequence"),
+        (r#""\U1234567""#, 10, "illegal character U+0022 '\"' in escape sequence"),
+        (r#""\uD800""#, 1, "escape sequence is invalid Unicode code point"),
+        (r#""\U00110000""#, 1, "escape sequence is invalid Unicode code point"),
+        (r#""\'""#, 1, r"unknown escape sequence \'"),
+        (r#""\é""#, 1, r"unknown escape sequence \é"),
+        (r#""\xff""#, 1, "invalid UTF-8 encoding"),
+        (r#""é\xc3""#, 3, "invalid UTF-8 encoding"),
+        (r#""a"b"#, 3, "invalid syntax"),
+        ("'", 1, "rune literal not terminated"),
+        ("''", 1, "empty rune literal or unescaped ' in rune literal"),
+        ("'ab'", 2, "more than one character in rune literal"),
+        ("'\\x1g'", 4, "illegal character U+0067 'g' in escape sequence"),
+        (r#"'\"'"#, 1, "unknown escape sequence \\\""),
+        ("'\n'", 1, "rune literal not terminated"),
+        ("`", 1, "raw string literal not terminated"),
+        ("`a", 2, "raw string literal not terminated"),
+        ("`a`b`", 2, "unexpected ` in raw string literal"),
+    ]
+}
+
+#[test]
+fn test_unquote(){
+    for (input, expected) in get_unquote_tests(){
+        match unquote(input){
+            Ok(s) => assert_eq!(s, expected, "unquote({:?})", input),
+            Err(e) => panic!("unquote({:?}) failed: {:?}", input, e),
+        }
+    }
+}
+
+#[test]
+fn test_unquote_errors(){
+    for (input, pos, msg) in get_misquoted_tests(){
+        match unquote(input){
+            Ok(s) => panic!("unquote({:?}) = {:?}, want error", input, s),
+            Err(e) => {
+                assert_eq!(e.msg, msg, "unquote({:?})", input);
+                assert_eq!(e.pos, pos, "unquote({:?})", input);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_unquote_bytes(){
+    // 单字节转义保留原始的字节，和 Go 一样不要求是合法的 UTF-8
+    assert_eq!(unquote_bytes(r#""\xff\377""#).unwrap(), vec![0xff, 0xff]);
+    assert_eq!(unquote_bytes(r#""ÿ""#).unwrap(), vec![0xc3, 0xbf]);
+    assert_eq!(unquote_bytes(r"'\x80'").unwrap(), vec![0x80]);
+    assert_eq!(unquote_bytes("`\\xff`").unwrap(), b"\\xff".to_vec());
+}
+
+#[test]
+fn test_unquote_char(){
+    assert_eq!(unquote_char("a'", '\'').unwrap(), ('a', false, "'"));
+    assert_eq!(unquote_char("é'", '\'').unwrap(), ('é', true, "'"));
+    assert_eq!(unquote_char(r"\n'", '\'').unwrap(), ('\n', false, "'"));
+    assert_eq!(unquote_char(r"\x80'", '\'').unwrap(), ('\u{0080}', false, "'"));
+    assert_eq!(unquote_char(r"\u00e9'", '\'').unwrap(), ('é', true, "'"));
+    assert_eq!(unquote_char(r#"\""#, '"').unwrap(), ('"', false, ""));
+    assert_eq!(unquote_char("'", '\'').unwrap_err().msg, "unescaped ' in literal");
+    assert_eq!(unquote_char("", '\'').unwrap_err().pos, 0);
+    assert_eq!(unquote_char("\\", '\'').unwrap_err().pos, 1);
+}
+
+struct NumberTest{
+    text: &'static str,
+    typ: ItemType,
+    is_int: bool,
+    is_uint: bool,
+    is_float: bool,
+    is_complex: bool,
+    int64: i64,
+    uint64: u64,
+    float64: f64,
+    complex128: (f64, f64),
+}
+
+fn number(text: &'static str, typ: ItemType, flags: (bool, bool, bool, bool), int64: i64, uint64: u64, float64: f64, complex128: (f64, f64)) -> NumberTest{
+    NumberTest{
+        text,
+        typ,
+        is_int: flags.0,
+        is_uint: flags.1,
+        is_float: flags.2,
+        is_complex: flags.3,
+        int64,
+        uint64,
+        float64,
+        complex128,
+    }
+}
+
+// 和 Go 的 parse_test.go 里的 numberTests 一致
+fn get_number_tests() -> Vec<NumberTest>{
+    let num = ItemType::ItemNumber;
+    let cpx = ItemType::ItemComplex;
+    let chr = ItemType::ItemCharConstant;
+    let int = (true, true, true, false);
+    let neg = (true, false, true, false);
+    let zero = (0.0, 0.0);
+    vec![
+        number("0", num, int, 0, 0, 0.0, zero),
+        number("-0", num, int, 0, 0, 0.0, zero),
+        number("73", num, int, 73, 73, 73.0, zero),
+        number("7_3", num, int, 73, 73, 73.0, zero),
+        number("0b10_010_01", num, int, 73, 73, 73.0, zero),
+        number("0B10_010_01", num, int, 73, 73, 73.0, zero),
+        number("073", num, int, 0o73, 0o73, 59.0, zero),
+        number("0o73", num, int, 0o73, 0o73, 59.0, zero),
+        number("0O73", num, int, 0o73, 0o73, 59.0, zero),
+        number("0x73", num, int, 0x73, 0x73, 115.0, zero),
+        number("0X73", num, int, 0x73, 0x73, 115.0, zero),
+        number("0x7_3", num, int, 0x73, 0x73, 115.0, zero),
+        number("-73", num, neg, -73, 0, -73.0, zero),
+        number("+73", num, neg, 73, 0, 73.0, zero),
+        number("100", num, int, 100, 100, 100.0, zero),
+        number("1e9", num, int, 1000000000, 1000000000, 1e9, zero),
+        number("-1e9", num, neg, -1000000000, 0, -1e9, zero),
+        number("-1.2", num, (false, false, true, false), 0, 0, -1.2, zero),
+        number("1e19", num, (false, true, true, false), 0, 10000000000000000000, 1e19, zero),
+        number("1e1_9", num, (false, true, true, false), 0, 10000000000000000000, 1e19, zero),
+        number("1E19", num, (false, true, true, false), 0, 10000000000000000000, 1e19, zero),
+        number("-1e19", num, (false, false, true, false), 0, 0, -1e19, zero),
+        number("0x_1p4", num, int, 16, 16, 16.0, zero),
+        number("0X_1P4", num, int, 16, 16, 16.0, zero),
+        number("0x_1p-4", num, (false, false, true, false), 0, 0, 1.0 / 16.0, zero),
+        number("0x1.8p1", num, int, 3, 3, 3.0, zero),
+        number("4i", num, (false, false, false, true), 0, 0, 0.0, (0.0, 4.0)),
+        number("-1.2+4.2i", cpx, (false, false, false, true), 0, 0, 0.0, (-1.2, 4.2)),
+        // 不是八进制
+        number("073i", num, (false, false, false, true), 0, 0, 0.0, (0.0, 73.0)),
+        // 虚部为 0 的复数也是浮点数，可能还是整数
+        number("0i", num, (true, true, true, true), 0, 0, 0.0, zero),
+        number("-1.2+0i", cpx, (false, false, true, true), 0, 0, -1.2, (-1.2, 0.0)),
+        number("-12+0i", cpx, (true, false, true, true), -12, 0, -12.0, (-12.0, 0.0)),
+        number("13+0i", cpx, (true, true, true, true), 13, 13, 13.0, (13.0, 0.0)),
+        number("0123", num, int, 0o123, 0o123, 83.0, zero),
+        number("-0x0", num, int, 0, 0, 0.0, zero),
+        number("0xdeadbeef", num, int, 0xdeadbeef, 0xdeadbeef, 3735928559.0, zero),
+        number("0xef", num, int, 0xef, 0xef, 239.0, zero),
+        number("18446744073709551615", num, (false, true, true, false), 0, 18446744073709551615, 18446744073709551615.0, zero),
+        number("-9223372036854775808", num, neg, -9223372036854775808, 0, -9223372036854775808.0, zero),
+        number("'a'", chr, int, 97, 97, 97.0, zero),
+        number(r"'\n'", chr, int, 10, 10, 10.0, zero),
+        number(r"'\\'", chr, int, 92, 92, 92.0, zero),
+        number(r"'\''", chr, int, 39, 39, 39.0, zero),
+        number(r"'\xFF'", chr, int, 0xFF, 0xFF, 255.0, zero),
+        number("'パ'", chr, int, 0x30d1, 0x30d1, 12497.0, zero),
+        number(r"'\u30d1'", chr, int, 0x30d1, 0x30d1, 12497.0, zero),
+        number(r"'\U000030d1'", chr, int, 0x30d1, 0x30d1, 12497.0, zero),
+    ]
+}
+
+#[test]
+fn test_parse_number(){
+    for test in get_number_tests(){
+        let n = match parse_number(test.text, test.typ){
+            Ok(n) => n,
+            Err(e) => panic!("{}: {}", test.text, e),
+        };
+        assert_eq!(n.is_int, test.is_int, "{}: is_int", test.text);
+        assert_eq!(n.is_uint, test.is_uint, "{}: is_uint", test.text);
+        assert_eq!(n.is_float, test.is_float, "{}: is_float", test.text);
+        assert_eq!(n.is_complex, test.is_complex, "{}: is_complex", test.text);
+        if test.is_int{
+            assert_eq!(n.int64, test.int64, "{}: int64", test.text);
+        }
+        if test.is_uint{
+            assert_eq!(n.uint64, test.uint64, "{}: uint64", test.text);
+        }
+        if test.is_float{
+            assert_eq!(n.float64, test.float64, "{}: float64", test.text);
+        }
+        if test.is_complex{
+            assert_eq!(n.complex128, test.complex128, "{}: complex128", test.text);
+        }
+    }
+}
+
+#[test]
+fn test_parse_number_errors(){
+    let tests = vec![
+        ("+-2", ItemType::ItemNumber, "illegal number syntax: \"+-2\""),
+        ("0x123.", ItemType::ItemNumber, "illegal number syntax: \"0x123.\""),
+        ("1e.", ItemType::ItemNumber, "illegal number syntax: \"1e.\""),
+        ("0xi.", ItemType::ItemNumber, "illegal number syntax: \"0xi.\""),
+        ("1+2.", ItemType::ItemNumber, "illegal number syntax: \"1+2.\""),
+        ("1__2", ItemType::ItemNumber, "illegal number syntax: \"1__2\""),
+        ("1e400", ItemType::ItemNumber, "illegal number syntax: \"1e400\""),
+        ("18446744073709551616", ItemType::ItemNumber, r#"integer overflow: "18446744073709551616""#),
+        ("-9223372036854775809", ItemType::ItemNumber, r#"integer overflow: "-9223372036854775809""#),
+        ("08", ItemType::ItemNumber, r#"integer overflow: "08""#),
+        ("0b1+1i", ItemType::ItemComplex, "syntax error scanning complex number"),
+        ("1+2", ItemType::ItemComplex, "syntax error scanning complex number"),
+        ("1_+2i", ItemType::ItemComplex, "strconv.ParseFloat: parsing \"1_\": invalid syntax"),
+        ("'x", ItemType::ItemCharConstant, "malformed character constant: 'x"),
+        ("'xx'", ItemType::ItemCharConstant, "malformed character constant: 'xx'"),
+        ("'433937734937734969526500969526500'", ItemType::ItemCharConstant, "malformed character constant: '433937734937734969526500969526500'"),
+    ];
+    for (text, typ, msg) in tests{
+        match parse_number(text, typ){
+            Ok(n) => panic!("{}: got {:?}, want error", text, n),
+            Err(e) => assert_eq!(e, msg, "{}", text),
+        }
+    }
+}